@@ -0,0 +1,128 @@
+use crate::{apt::Upgradeable, error::Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+const ACKS_FILE: &str = "acks.json";
+
+/// A deliberately deferred update.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Acknowledgement {
+    pub package: String,
+    /// Candidate version that was acknowledged
+    pub version: String,
+    /// Last day the acknowledgement applies
+    pub until: Option<NaiveDate>,
+    pub reason: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+impl Acknowledgement {
+    /// Whether this acknowledgement hides `pkg` on `today`.
+    ///
+    /// It stops applying once the candidate version changes or the snooze expires.
+    pub fn applies(&self, pkg: &Upgradeable, today: NaiveDate) -> bool {
+        self.package == pkg.name
+            && pkg.candidate.as_deref() == Some(self.version.as_str())
+            && self.until.is_none_or(|until| today <= until)
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Acknowledgements(Vec<Acknowledgement>);
+
+impl Acknowledgements {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let file = dir.join(ACKS_FILE);
+
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(ACKS_FILE), serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Adds an acknowledgement, replacing any existing one for the same package.
+    pub fn add(&mut self, ack: Acknowledgement) {
+        self.remove(&ack.package);
+        self.0.push(ack);
+        self.0.sort_by(|a, b| a.package.cmp(&b.package));
+    }
+
+    /// Removes the acknowledgement for `package`, returning whether there was one.
+    pub fn remove(&mut self, package: &str) -> bool {
+        let count = self.0.len();
+        self.0.retain(|ack| ack.package != package);
+        count != self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Acknowledgement> {
+        self.0.iter()
+    }
+
+    /// Sets `acknowledged` on the packages with an applicable acknowledgement.
+    pub fn apply(&self, packages: &mut [Upgradeable], today: NaiveDate) {
+        for pkg in packages.iter_mut() {
+            pkg.acknowledged = self.0.iter().find(|ack| ack.applies(pkg, today)).cloned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(version: &str, until: Option<NaiveDate>) -> Acknowledgement {
+        Acknowledgement {
+            package: String::from("postgresql-15"),
+            version: version.to_string(),
+            until,
+            reason: None,
+            created: Utc::now(),
+        }
+    }
+
+    fn upgradeable(candidate: &str) -> Upgradeable {
+        Upgradeable {
+            name: String::from("postgresql-15"),
+            candidate: Some(candidate.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn applies_to_the_acknowledged_candidate() {
+        let today = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+
+        assert!(ack("15.6-0+deb12u1", None).applies(&upgradeable("15.6-0+deb12u1"), today));
+        assert!(!ack("15.6-0+deb12u1", None).applies(&upgradeable("15.7-0+deb12u1"), today));
+    }
+
+    #[test]
+    fn expires_after_until() {
+        let until = NaiveDate::from_ymd_opt(2024, 5, 1);
+        let pkg = upgradeable("15.6-0+deb12u1");
+
+        assert!(ack("15.6-0+deb12u1", until).applies(&pkg, until.unwrap()));
+        assert!(!ack("15.6-0+deb12u1", until).applies(&pkg, until.unwrap().succ_opt().unwrap()));
+    }
+
+    #[test]
+    fn add_replaces_and_remove() {
+        let mut acks = Acknowledgements::default();
+        acks.add(ack("15.6-0+deb12u1", None));
+        acks.add(ack("15.7-0+deb12u1", None));
+
+        assert_eq!(acks.iter().count(), 1);
+        assert_eq!(acks.iter().next().unwrap().version, "15.7-0+deb12u1");
+        assert!(acks.remove("postgresql-15"));
+        assert!(!acks.remove("postgresql-15"));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::error::{Error, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const SPOOL_DIR: &str = "spool";
+
+#[cfg(feature = "http")]
+type Client = ureq::Agent;
+
+/// Sending reports requires the `http` feature.
+#[cfg(not(feature = "http"))]
+struct Client;
+
+/// Ships reports to a central collector instead of sending email.
+#[derive(Deserialize, Serialize)]
+pub struct Agent {
+    /// Collector endpoint the reports are POSTed to
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Client certificate (PEM) for mutual TLS
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    /// Private key (PEM, PKCS#8) of `cert`
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// CA certificate (PEM) trusted for the collector, in addition to the system ones
+    #[serde(default)]
+    pub ca: Option<PathBuf>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Oldest reports are dropped from the spool beyond this many
+    #[serde(default = "default_max_spooled")]
+    pub max_spooled: usize,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_spooled() -> usize {
+    100
+}
+
+/// Outcome of [`Agent::ship`].
+pub struct Shipped {
+    pub sent: usize,
+    /// Reports kept in the spool to be retried on the next run
+    pub spooled: usize,
+    /// Reports the collector refused (4xx), which are dropped
+    pub rejected: usize,
+    /// Spooled reports that couldn't be read, renamed to `.corrupt` so that they aren't
+    /// retried again
+    pub quarantined: Vec<PathBuf>,
+    /// The last error, if any report wasn't sent
+    pub error: Option<Error>,
+}
+
+/// Why a report wasn't sent.
+enum Failure {
+    /// The collector refused it; sending it again won't help
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    Rejected(Error),
+    /// A network error or a 5xx response
+    Transient(Error),
+}
+
+impl Agent {
+    /// Sends the reports spooled by previous runs, then `reports` (as JSON).
+    ///
+    /// Once a report can't be sent because of a network error or a 5xx response, it and the
+    /// remaining ones are spooled in `dir` to be retried on the next run. Reports the
+    /// collector refuses (4xx) are dropped.
+    pub fn ship(&self, dir: &Path, reports: Vec<String>) -> Result<Shipped> {
+        let spool = dir.join(SPOOL_DIR);
+        fs::create_dir_all(&spool)?;
+
+        let mut spooled: Vec<PathBuf> = fs::read_dir(&spool)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        spooled.sort();
+
+        let client = self.client()?;
+        let mut sent = 0;
+        let mut rejected = 0;
+        let mut quarantined = Vec::new();
+        let mut error = None;
+        let mut retry_later = false;
+
+        for path in &spooled {
+            let report = fs::read_to_string(path)
+                .ok()
+                .filter(|report| serde_json::from_str::<serde::de::IgnoredAny>(report).is_ok());
+            let Some(report) = report else {
+                let corrupt = path.with_extension("corrupt");
+                fs::rename(path, &corrupt)?;
+                quarantined.push(corrupt);
+                continue;
+            };
+
+            match self.send(&client, &report) {
+                Ok(()) => sent += 1,
+                Err(Failure::Rejected(err)) => {
+                    rejected += 1;
+                    error = Some(err);
+                }
+                Err(Failure::Transient(err)) => {
+                    error = Some(err);
+                    retry_later = true;
+                    break;
+                }
+            }
+
+            fs::remove_file(path)?;
+        }
+
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.f");
+
+        for (i, report) in reports.iter().enumerate() {
+            if !retry_later {
+                match self.send(&client, report) {
+                    Ok(()) => {
+                        sent += 1;
+                        continue;
+                    }
+                    Err(Failure::Rejected(err)) => {
+                        rejected += 1;
+                        error = Some(err);
+                        continue;
+                    }
+                    Err(Failure::Transient(err)) => {
+                        error = Some(err);
+                        retry_later = true;
+                    }
+                }
+            }
+
+            fs::write(spool.join(format!("{}-{}.json", timestamp, i)), report)?;
+        }
+
+        Ok(Shipped {
+            sent,
+            spooled: self.trim_spool(&spool)?,
+            rejected,
+            quarantined,
+            error,
+        })
+    }
+
+    /// Drops the oldest spooled reports beyond `max_spooled`, returning how many are left.
+    fn trim_spool(&self, spool: &Path) -> Result<usize> {
+        let mut spooled: Vec<PathBuf> = fs::read_dir(spool)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        spooled.sort();
+
+        let excess = spooled.len().saturating_sub(self.max_spooled);
+        for path in &spooled[..excess] {
+            fs::remove_file(path)?;
+        }
+
+        Ok(spooled.len() - excess)
+    }
+
+    #[cfg(feature = "http")]
+    fn client(&self) -> Result<Client> {
+        let mut tls = native_tls::TlsConnector::builder();
+
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            let identity = native_tls::Identity::from_pkcs8(&fs::read(cert)?, &fs::read(key)?)
+                .map_err(|err| Error::Http(format!("{}: {}", cert.display(), err)))?;
+            tls.identity(identity);
+        }
+
+        if let Some(ca) = &self.ca {
+            let certificate = native_tls::Certificate::from_pem(&fs::read(ca)?)
+                .map_err(|err| Error::Http(format!("{}: {}", ca.display(), err)))?;
+            tls.add_root_certificate(certificate);
+        }
+
+        let tls = tls.build().map_err(|err| Error::Http(err.to_string()))?;
+
+        Ok(ureq::AgentBuilder::new()
+            .tls_connector(std::sync::Arc::new(tls))
+            .timeout(std::time::Duration::from_secs(self.timeout_secs))
+            .build())
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn client(&self) -> Result<Client> {
+        Err(Error::Http(String::from(
+            "built without the `http` feature",
+        )))
+    }
+
+    #[cfg(feature = "http")]
+    fn send(&self, client: &Client, report: &str) -> std::result::Result<(), Failure> {
+        let mut request = client
+            .post(&self.url)
+            .set("Content-Type", "application/json");
+
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+
+        match request.send_string(report) {
+            Ok(_) => Ok(()),
+            Err(err @ ureq::Error::Status(400..=499, _)) => {
+                Err(Failure::Rejected(Error::Http(err.to_string())))
+            }
+            Err(err) => Err(Failure::Transient(Error::Http(err.to_string()))),
+        }
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn send(&self, _client: &Client, _report: &str) -> std::result::Result<(), Failure> {
+        Err(Failure::Transient(Error::Http(String::from(
+            "built without the `http` feature",
+        ))))
+    }
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+    use std::{io::Write, net::TcpListener, thread};
+
+    fn agent(url: String) -> Agent {
+        Agent {
+            url,
+            token: None,
+            cert: None,
+            key: None,
+            ca: None,
+            timeout_secs: 5,
+            max_spooled: default_max_spooled(),
+        }
+    }
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-agent-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// A collector answering each of `requests` requests with `status`.
+    fn collector(status: &'static str, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/reports", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let _ = std::io::Read::read(&mut stream, &mut [0; 65536]);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn spools_when_unreachable() {
+        let dir = spool_dir("unreachable");
+        // Nothing listens on the discard port
+        let agent = agent(String::from("http://127.0.0.1:9/reports"));
+
+        let shipped = agent.ship(&dir, vec![String::from("{}")]).unwrap();
+
+        assert_eq!((shipped.sent, shipped.spooled, shipped.rejected), (0, 1, 0));
+        assert!(shipped.error.is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drops_rejected_reports() {
+        let dir = spool_dir("rejected");
+        fs::create_dir_all(dir.join(SPOOL_DIR)).unwrap();
+        fs::write(dir.join(SPOOL_DIR).join("20240501T060000-0.json"), "{}").unwrap();
+        let agent = agent(collector("400 Bad Request", 2));
+
+        let shipped = agent.ship(&dir, vec![String::from("{}")]).unwrap();
+
+        assert_eq!((shipped.sent, shipped.spooled, shipped.rejected), (0, 0, 2));
+        assert!(shipped.error.is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn spools_on_server_errors() {
+        let dir = spool_dir("unavailable");
+        let agent = agent(collector("503 Service Unavailable", 1));
+
+        let shipped = agent
+            .ship(&dir, vec![String::from("{}"), String::from("{}")])
+            .unwrap();
+
+        assert_eq!((shipped.sent, shipped.spooled, shipped.rejected), (0, 2, 0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quarantines_unreadable_reports() {
+        let dir = spool_dir("corrupt");
+        let spool = dir.join(SPOOL_DIR);
+        fs::create_dir_all(&spool).unwrap();
+        fs::write(spool.join("20240501T060000-0.json"), b"\xff\xfe").unwrap();
+        fs::write(
+            spool.join("20240501T060000-1.json"),
+            "{\"version\": 1, \"pack",
+        )
+        .unwrap();
+        fs::write(spool.join("20240501T060000-2.json"), "{}").unwrap();
+        let agent = agent(collector("204 No Content", 2));
+
+        let shipped = agent.ship(&dir, vec![String::from("{}")]).unwrap();
+
+        assert_eq!((shipped.sent, shipped.spooled, shipped.rejected), (2, 0, 0));
+        assert_eq!(
+            shipped.quarantined,
+            vec![
+                spool.join("20240501T060000-0.corrupt"),
+                spool.join("20240501T060000-1.corrupt")
+            ]
+        );
+        assert!(shipped.error.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trim_keeps_the_newest() {
+        let dir = spool_dir("trim");
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["1.json", "2.json", "3.json"] {
+            fs::write(dir.join(name), "{}").unwrap();
+        }
+        let agent = Agent {
+            max_spooled: 2,
+            ..agent(String::new())
+        };
+
+        assert_eq!(agent.trim_spool(&dir).unwrap(), 2);
+        assert!(!dir.join("1.json").exists());
+        assert!(dir.join("3.json").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
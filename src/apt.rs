@@ -1,18 +1,31 @@
-use crate::error::{Error, Result};
+use crate::{
+    ack::Acknowledgement,
+    cve::{Cve, Severity},
+    error::{Error, Result},
+    news::Entry,
+    phasing,
+    unattended::{self, Origin},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use rust_apt::{
     cache::PackageSort,
+    config::Config,
     new_cache,
+    raw::package::RawPackageFile,
     raw::progress::AcquireProgress,
-    util::{time_str, unit_str, NumSys},
+    util::{cmp_versions, time_str, unit_str, NumSys},
 };
 use std::{
+    cmp::Ordering,
     fmt,
+    path::Path,
     sync::{Arc, Mutex},
 };
 
 /// acquire-item.h
 #[repr(u32)]
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub enum ItemState {
     /// The item is waiting to be downloaded.
     StatIdle,
@@ -51,25 +64,56 @@ impl TryFrom<u32> for ItemState {
     }
 }
 
-#[derive(Clone, Debug)]
+/// A line of `apt update` output.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum ProgressOutput {
-    Hit(u32, String),
-    Fetch(u32, String, u64),
-    Stop(u64, u64, u64),
-    Fail(u32, String, ItemState, String),
+    /// The item is up-to-date (`Hit:`).
+    Hit {
+        id: u32,
+        description: String,
+    },
+    /// The item was downloaded (`Get:`).
+    Fetch {
+        id: u32,
+        description: String,
+        file_size: u64,
+    },
+    /// The update finished (`Fetched ...`).
+    Stop {
+        fetched_bytes: u64,
+        elapsed_time: u64,
+        current_cps: u64,
+    },
+    /// The item was ignored or could not be downloaded (`Ign:` or `Err:`).
+    Fail {
+        id: u32,
+        description: String,
+        status: ItemState,
+        error_text: String,
+    },
+    /// An error or warning from apt (`E:`, `W:`, ...).
     Error(OutputError),
 }
 
 impl fmt::Display for ProgressOutput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Hit(id, description) => write!(f, "Hit:{id} {description}"),
-            Self::Fetch(id, description, file_size) => write!(
+            Self::Hit { id, description } => write!(f, "Hit:{id} {description}"),
+            Self::Fetch {
+                id,
+                description,
+                file_size,
+            } => write!(
                 f,
                 "Get:{id} {description} [{}]",
                 unit_str(*file_size, rust_apt::util::NumSys::Decimal)
             ),
-            Self::Stop(fetched_bytes, elapsed_time, current_cps) => {
+            Self::Stop {
+                fetched_bytes,
+                elapsed_time,
+                current_cps,
+            } => {
                 if *fetched_bytes != 0 {
                     write!(
                         f,
@@ -82,24 +126,25 @@ impl fmt::Display for ProgressOutput {
                     write!(f, "Nothing to fetch.")
                 }
             }
-            Self::Fail(id, description, status, _error_text) => {
+            Self::Fail {
+                id,
+                description,
+                status,
+                ..
+            } => {
                 if *status == ItemState::StatIdle || *status == ItemState::StatDone {
-                    return write!(f, "Ign:{id} {description}");
+                    write!(f, "Ign:{id} {description}")
                 } else {
-                    return write!(f, "Err:{id} {description}");
+                    write!(f, "Err:{id} {description}")
                 }
             }
-            Self::Error(err) => match err {
-                OutputError::Error(msg) => write!(f, "E: {}", &msg[2..]),
-                OutputError::Warning(msg) => write!(f, "W: {}", &msg[2..]),
-                OutputError::Notice(msg) => write!(f, "N: {}", &msg[2..]),
-                OutputError::Debug(msg) => write!(f, "D: {}", &msg[2..]),
-            },
+            Self::Error(err) => write!(f, "{}", err),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "level", content = "message", rename_all = "lowercase")]
 pub enum OutputError {
     Error(String),
     Warning(String),
@@ -107,24 +152,52 @@ pub enum OutputError {
     Debug(String),
 }
 
+impl OutputError {
+    /// The message without the level, also for reports from agents that kept apt's `E:` prefix.
+    pub fn message(&self) -> &str {
+        let (Self::Error(msg) | Self::Warning(msg) | Self::Notice(msg) | Self::Debug(msg)) = self;
+
+        ["E:", "W:", "N:", "D:"]
+            .iter()
+            .find_map(|prefix| msg.strip_prefix(prefix))
+            .unwrap_or(msg)
+            .trim()
+    }
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error(_) => write!(f, "E: {}", self.message()),
+            Self::Warning(_) => write!(f, "W: {}", self.message()),
+            Self::Notice(_) => write!(f, "N: {}", self.message()),
+            Self::Debug(_) => write!(f, "D: {}", self.message()),
+        }
+    }
+}
+
 impl TryFrom<&str> for OutputError {
     type Error = String;
 
     fn try_from(value: &str) -> std::result::Result<Self, String> {
-        if value.starts_with("E:") {
-            Ok(Self::Error(value.to_string()))
-        } else if value.starts_with("W:") {
-            Ok(Self::Warning(value.to_string()))
-        } else if value.starts_with("N:") {
-            Ok(Self::Notice(value.to_string()))
-        } else if value.starts_with("D:") {
-            Ok(Self::Debug(value.to_string()))
+        if let Some(msg) = value.strip_prefix("E:") {
+            Ok(Self::Error(msg.trim().to_string()))
+        } else if let Some(msg) = value.strip_prefix("W:") {
+            Ok(Self::Warning(msg.trim().to_string()))
+        } else if let Some(msg) = value.strip_prefix("N:") {
+            Ok(Self::Notice(msg.trim().to_string()))
+        } else if let Some(msg) = value.strip_prefix("D:") {
+            Ok(Self::Debug(msg.trim().to_string()))
         } else {
             Err(value.to_string())
         }
     }
 }
 
+/// Updates the package lists, like `apt update`.
+///
+/// Errors from apt while fetching are returned as [`ProgressOutput::Error`] items;
+/// only a failure to open the cache is returned as an [`Error`].
 pub fn update() -> Result<Vec<ProgressOutput>> {
     let cache = new_cache!().map_err(Error::AptCache)?;
 
@@ -137,40 +210,229 @@ pub fn update() -> Result<Vec<ProgressOutput>> {
     let mut output = output.lock().unwrap().clone();
 
     if let Err(err) = result {
-        output.extend(
-            err.what()
-                .split(';')
-                .map(|msg| ProgressOutput::Error(OutputError::try_from(msg).unwrap())),
-        );
+        output.extend(err.what().split(';').map(|msg| {
+            ProgressOutput::Error(OutputError::try_from(msg).unwrap_or_else(OutputError::Error))
+        }));
     }
 
     Ok(output)
 }
 
+/// Points apt at an extracted filesystem (e.g. a chroot or container image) instead of the host.
+///
+/// Must be called before any other function in this module; the dpkg status and package
+/// lists are then read from `dir`, and other host files (e.g. `/etc/os-release`) are read
+/// from it as well. The apt configuration is read again from the root's `apt.conf` and
+/// `apt.conf.d`, discarding everything set before.
+pub fn set_root(dir: &Path) -> Result<()> {
+    let dir = dir.canonicalize()?;
+
+    // The configuration has already been initialized from the host's files, which are
+    // only looked up under RootDir if it is set first
+    let config = Config::new();
+    config.clear_all();
+    config.set("RootDir", &dir.to_string_lossy());
+    rust_apt::config::init_config_system();
+
+    Ok(())
+}
+
+/// Lists the packages that can be upgraded, and those a pin keeps below the newest version
+/// in the sources, sorted by name.
 pub fn list_upgradeable() -> Result<Vec<Upgradeable>> {
     let cache = new_cache!().map_err(Error::AptCache)?;
-    let sort = PackageSort::default().upgradable().names();
+    let sort = PackageSort::default().installed().names();
+
+    let unattended = unattended::Policy::from_apt_config();
+    let phasing = phasing::Policy::from_apt_config();
+
+    Ok(cache
+        .packages(&sort)
+        .filter(|package| package.is_upgradable() || available(package).is_some())
+        .map(|package| {
+            let upgradable = package.is_upgradable();
+            let origins: Vec<Origin> = package
+                .candidate()
+                .map(|v| v.package_files().map(origin).collect())
+                .unwrap_or_default();
+            let security = origins.iter().any(|o| o.archive.ends_with("-security"));
+
+            let mut upgradeable = Upgradeable::from(package);
+            if !upgradable {
+                // Pinned at the installed version: nothing would be downloaded or installed
+                upgradeable.download_size = 0;
+                upgradeable.installed_size_delta = 0;
+                return upgradeable;
+            }
+
+            upgradeable.phasing_deferred = upgradeable.phased_percentage.is_some_and(|percentage| {
+                phasing.defers(
+                    &upgradeable.source_name,
+                    &upgradeable.source_version,
+                    percentage,
+                    security,
+                )
+            });
+            // unattended-upgrades follows apt's phasing
+            upgradeable.auto_upgrade =
+                !upgradeable.phasing_deferred && unattended.allows(&upgradeable.name, &origins);
+            upgradeable.manual = unattended.enabled() && !upgradeable.auto_upgrade;
+            upgradeable
+        })
+        .collect())
+}
+
+fn origin(file: RawPackageFile) -> Origin {
+    let field = |value: std::result::Result<&str, cxx::Exception>| {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    };
+
+    Origin {
+        origin: field(file.origin()),
+        archive: field(file.archive()),
+        codename: field(file.codename()),
+        label: field(file.label()),
+        component: field(file.component()),
+        site: field(file.site()),
+    }
+}
+
+/// The newest downloadable version of `package`, if the candidate apt picked by pin priority
+/// is older.
+fn available(package: &rust_apt::package::Package) -> Option<String> {
+    let candidate = package.candidate()?;
+
+    package
+        .versions()
+        .find(|v| v.is_downloadable())
+        .filter(|newest| cmp_versions(newest.version(), candidate.version()) == Ordering::Greater)
+        .map(|newest| newest.version().to_string())
+}
+
+/// Returns the candidate version of `name`, if the package exists.
+pub fn candidate(name: &str) -> Result<Option<String>> {
+    let cache = new_cache!().map_err(Error::AptCache)?;
 
-    Ok(cache.packages(&sort).map(Into::<Upgradeable>::into).collect())
+    Ok(cache
+        .get(name)
+        .and_then(|pkg| pkg.candidate().map(|v| v.version().to_string())))
 }
 
+/// `pkgCache::State::Hold`
+const SELECTED_STATE_HOLD: u8 = 2;
+
+/// A package with a newer candidate version.
+#[derive(Default, Deserialize, Serialize)]
 pub struct Upgradeable {
     pub name: String,
+    /// Installed version
     pub installed: Option<String>,
+    /// Version that would be installed by an upgrade
     pub candidate: Option<String>,
+    /// Archives (e.g. `bookworm-security`) the candidate is available from, comma separated
     pub archive: String,
+    /// Origins (e.g. `Debian`) the candidate is available from, comma separated
+    #[serde(default)]
+    pub origin: String,
     pub arch: String,
+    #[serde(default)]
+    pub section: Option<String>,
+    /// Source package the candidate was built from
+    #[serde(default)]
+    pub source_name: String,
+    #[serde(default)]
+    pub source_version: String,
+    /// Debian priority of the candidate (e.g. `optional`)
+    #[serde(default)]
+    pub priority: Option<String>,
+    /// Held with `apt-mark hold` (dpkg selection state)
+    #[serde(default)]
+    pub held: bool,
+    /// Pin priority of the candidate, as shown by `apt policy`
+    #[serde(default)]
+    pub pin_priority: Option<i32>,
+    /// A pin keeps the candidate below the newest version in the sources
+    #[serde(default)]
+    pub pinned: bool,
+    /// Newest version in the sources, if [`Upgradeable::pinned`]
+    #[serde(default)]
+    pub available: Option<String>,
+    /// Size of the candidate's `.deb` in bytes
+    #[serde(default)]
+    pub download_size: u64,
+    /// Change in installed size in bytes, compared to the installed version
+    #[serde(default)]
+    pub installed_size_delta: i64,
+    /// `Phased-Update-Percentage` of the candidate
+    #[serde(default)]
+    pub phased_percentage: Option<u32>,
+    /// apt holds the update back on this machine until more machines have received it
+    #[serde(default)]
+    pub phasing_deferred: bool,
+    /// CVEs fixed by the update, see [`crate::cve`]
+    #[serde(default)]
+    pub cves: Vec<Cve>,
+    /// `NEWS.Debian` entries newer than the installed version, see [`crate::news`]
+    #[serde(default)]
+    pub news: Vec<Entry>,
+    /// Will be installed automatically by unattended-upgrades
+    #[serde(default)]
+    pub auto_upgrade: bool,
+    /// unattended-upgrades is enabled but won't install the update
+    #[serde(default)]
+    pub manual: bool,
+    /// When this candidate was first seen, see [`crate::state::Pending`]
+    #[serde(default)]
+    pub pending_since: Option<DateTime<Utc>>,
+    /// Set if the update has been deliberately deferred, see [`crate::ack`]
+    #[serde(default)]
+    pub acknowledged: Option<Acknowledgement>,
+}
+
+impl Upgradeable {
+    /// Held back deliberately, with `apt-mark hold` or a pin.
+    pub fn is_held(&self) -> bool {
+        self.held || self.pinned
+    }
+
+    /// Whether the candidate comes from a security archive (e.g. `bookworm-security`).
+    pub fn is_security(&self) -> bool {
+        self.archive.split(',').any(|a| a.ends_with("-security"))
+    }
+
+    /// Severity of the most severe CVE fixed by the update.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.cves.iter().map(|cve| cve.severity).max()
+    }
+
+    /// Number of whole days the update has been pending for at `now`.
+    pub fn pending_days(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.pending_since.map(|since| (now - since).num_days())
+    }
 }
 
 impl From<rust_apt::package::Package<'_>> for Upgradeable {
     fn from(package: rust_apt::package::Package) -> Self {
 
-        let inst = package.versions().next().unwrap();
-        let package_files = inst.package_files();
+        let candidate = package.candidate();
 
-        let archives: Vec<String> = package_files.filter_map(|p| p.archive().ok().map(|v| v.to_string()).or(Some("unknown".to_string()))).collect();
+        let archives: Vec<String> = candidate
+            .iter()
+            .flat_map(|v| v.package_files())
+            .map(|p| p.archive().map(|v| v.to_string()).unwrap_or("unknown".to_string()))
+            .collect();
         let archive = archives.join(",");
 
+        let origins: Vec<String> = candidate
+            .iter()
+            .flat_map(|v| v.package_files())
+            .filter_map(|p| p.origin().ok().map(|v| v.to_string()))
+            .collect();
+        let origin = origins.join(",");
+
+        let installed_size = package.installed().map(|v| v.installed_size()).unwrap_or(0);
+        let available = available(&package);
+
         Self {
             name: package.name().to_string(),
             installed: package
@@ -183,6 +445,41 @@ impl From<rust_apt::package::Package<'_>> for Upgradeable {
             .candidate()
             .map(|v| v.arch().to_string()).unwrap_or_default(),
             archive,
+            origin,
+            section: candidate
+                .as_ref()
+                .and_then(|v| v.section().ok().map(|s| s.to_string())),
+            priority: candidate
+                .as_ref()
+                .and_then(|v| v.priority_str().ok().map(|s| s.to_string())),
+            held: package.selected_state() == SELECTED_STATE_HOLD,
+            pin_priority: candidate.as_ref().map(|v| v.priority()),
+            pinned: available.is_some(),
+            available,
+            source_name: candidate
+                .as_ref()
+                .map(|v| v.source_name().to_string())
+                .unwrap_or_default(),
+            source_version: candidate
+                .as_ref()
+                .map(|v| v.source_version().to_string())
+                .unwrap_or_default(),
+            download_size: candidate.as_ref().map(|v| v.size()).unwrap_or(0),
+            installed_size_delta: candidate
+                .as_ref()
+                .map(|v| v.installed_size() as i64 - installed_size as i64)
+                .unwrap_or(0),
+            phased_percentage: candidate
+                .as_ref()
+                .and_then(|v| v.get_record("Phased-Update-Percentage"))
+                .and_then(|percentage| percentage.trim().parse().ok()),
+            phasing_deferred: false,
+            cves: Vec::new(),
+            news: Vec::new(),
+            auto_upgrade: false,
+            manual: false,
+            pending_since: None,
+            acknowledged: None,
         }
     }
 }
@@ -207,14 +504,21 @@ impl AcquireProgress for AptAcquireProgress {
 
     /// Called when an item is confirmed to be up-to-date.
     fn hit(&mut self, id: u32, description: String) {
-        self.output.lock().unwrap().push(ProgressOutput::Hit(id, description));
+        self.output
+            .lock()
+            .unwrap()
+            .push(ProgressOutput::Hit { id, description });
     }
 
     /// Called when an Item has started to download
     ///
     /// Prints out the short description and the expected size.
     fn fetch(&mut self, id: u32, description: String, file_size: u64) {
-        self.output.lock().unwrap().push(ProgressOutput::Fetch(id, description, file_size));
+        self.output.lock().unwrap().push(ProgressOutput::Fetch {
+            id,
+            description,
+            file_size,
+        });
     }
 
     /// Called when an item is successfully and completely fetched.
@@ -235,23 +539,23 @@ impl AcquireProgress for AptAcquireProgress {
             return;
         }
 
-        self.output.lock().unwrap().push(ProgressOutput::Stop(
+        self.output.lock().unwrap().push(ProgressOutput::Stop {
             fetched_bytes,
             elapsed_time,
             current_cps,
-        ));
+        });
     }
 
     /// Called when an Item fails to download.
     ///
     /// Print out the ErrorText for the Item.
     fn fail(&mut self, id: u32, description: String, status: u32, error_text: String) {
-        self.output.lock().unwrap().push(ProgressOutput::Fail(
+        self.output.lock().unwrap().push(ProgressOutput::Fail {
             id,
             description,
-            ItemState::try_from(status).unwrap(),
+            status: ItemState::try_from(status).unwrap(),
             error_text,
-        ));
+        });
     }
 
     /// Called periodically to provide the overall progress information
@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 /// Sends notification containing upgradeable packages.
 #[derive(Parser, Debug)]
@@ -8,14 +10,121 @@ pub struct Cli {
     #[arg(short, long, default_value = "/etc/pkg-updates-notifier.conf")]
     pub config: String,
 
+    /// Where to send the report
+    #[arg(short, long, value_enum, default_value_t = Output::Mail)]
+    pub output: Output,
+
+    /// Check an extracted filesystem (chroot, container image) instead of the host, without updating the package lists
+    #[arg(long, global = true)]
+    pub root: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub command: Option<Command>,
 }
 
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum Output {
+    /// Send the report by email
+    Mail,
+    /// Print the report as JSON
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Set config
     Configure,
+    /// Run from an APT hook
+    Hook {
+        #[clap(subcommand)]
+        hook: Hook,
+    },
+    /// Send the report to the collector configured in [agent] instead of by email
+    Agent,
+    /// Send the notification queued during the quiet hours in [schedule], once they are over,
+    /// and retry the notifications in the outbox
+    Flush {
+        /// Only retry the outbox notifications that are due, as a normal run does
+        #[arg(long)]
+        due: bool,
+    },
+    /// Acknowledge (snooze) the update of a package, or list or remove acknowledgements
+    Ack(AckArgs),
+    /// Inspect the apt sources
+    Sources {
+        #[clap(subcommand)]
+        command: SourcesCommand,
+    },
+    /// Serve the report of this host over HTTP, refreshing it periodically
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Address to listen on (default: `listen` in [serve])
+        #[arg(long)]
+        listen: Option<String>,
+    },
+    /// Check on the schedule in [daemon] and whenever the package lists or installed packages change
+    #[cfg(feature = "daemon")]
+    Daemon,
+    /// Central collector for the reports sent by agents
+    #[cfg(feature = "collector")]
+    Collector {
+        #[clap(subcommand)]
+        command: CollectorCommand,
+    },
+}
+
+#[cfg(feature = "collector")]
+#[derive(Subcommand, Debug)]
+pub enum CollectorCommand {
+    /// Receive reports and send the daily digest
+    Serve {
+        /// Address to listen on (default: `listen` in [collector])
+        #[arg(long)]
+        listen: Option<String>,
+    },
+    /// Send the digest now
+    Digest,
+}
+
+#[derive(Args, Debug)]
+pub struct AckArgs {
+    /// Package name
+    #[arg(required_unless_present = "list")]
+    pub package: Option<String>,
+
+    /// Candidate version to acknowledge (default: the current candidate)
+    #[arg(long)]
+    pub version: Option<String>,
+
+    /// Last day the acknowledgement applies (YYYY-MM-DD)
+    #[arg(long)]
+    pub until: Option<NaiveDate>,
+
+    /// Why the update is deferred
+    #[arg(long)]
+    pub reason: Option<String>,
+
+    /// List acknowledged updates
+    #[arg(long, conflicts_with_all = ["package", "remove", "version", "until", "reason"])]
+    pub list: bool,
+
+    /// Remove the acknowledgement of the package
+    #[arg(long, conflicts_with_all = ["version", "until", "reason"])]
+    pub remove: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SourcesCommand {
+    /// Flag end-of-life suites, deprecated or missing keyrings, insecure, duplicate and stale sources
+    Audit,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Hook {
+    /// Snapshot the dpkg status (DPkg::Pre-Invoke)
+    PreDpkg,
+    /// Send a summary of the packages changed since the snapshot (DPkg::Post-Invoke)
+    PostDpkg,
 }
 
 pub(crate) fn parse() -> Cli {
@@ -23,8 +132,9 @@ pub(crate) fn parse() -> Cli {
 }
 
 pub mod commands {
-    use super::Cli;
-    use crate::Result;
+    use super::{AckArgs, Cli, Output};
+    use crate::{ack, apt, dpkg, mail, Result};
+    use chrono::Utc;
     use dialoguer::{theme::ColorfulTheme, Input, Password};
     use lettre::message::Mailbox;
 
@@ -60,7 +170,7 @@ pub mod commands {
             .with_initial_text(config.mail.from.clone())
             .validate_with(|input: &String| -> std::result::Result<(), String> {
                 if let Err(err) = input.parse::<Mailbox>() {
-                    return Err(format!("Invalid address: {}", err.to_string()));
+                    return Err(format!("Invalid address: {}", err));
                 }
                 Ok(())
             })
@@ -72,7 +182,7 @@ pub mod commands {
             .with_initial_text(config.mail.to().to_string())
             .validate_with(|input: &String| -> std::result::Result<(), String> {
                 if let Err(err) = input.parse::<Mailbox>() {
-                    return Err(format!("Invalid address: {}", err.to_string()));
+                    return Err(format!("Invalid address: {}", err));
                 }
                 Ok(())
             })
@@ -91,10 +201,169 @@ pub mod commands {
         let config = crate::config::Config {
             smtp: crate::config::SMTP::new(smtp_relay, smtp_username, smtp_password),
             mail: crate::config::Mail::new(mail_from, vec![mail_to], mail_subject),
+            ..config
         };
 
         config.to_file(&cli.config)?;
 
         Ok(())
     }
+
+    const DPKG_SNAPSHOT: &str = "dpkg-status.pre";
+
+    pub fn hook_pre_dpkg(cli: &Cli) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        std::fs::create_dir_all(config.state.dir())?;
+        std::fs::copy(dpkg::status_file(), config.state.dir().join(DPKG_SNAPSHOT))?;
+
+        Ok(())
+    }
+
+    pub fn hook_post_dpkg(cli: &Cli) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let snapshot = config.state.dir().join(DPKG_SNAPSHOT);
+
+        if !snapshot.exists() {
+            return Ok(());
+        }
+
+        let before = dpkg::read_status(&snapshot)?;
+        let after = dpkg::read_status(dpkg::status_file())?;
+        std::fs::remove_file(&snapshot)?;
+
+        let changes = dpkg::diff(&before, &after);
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let history = dpkg::last_history_entry(dpkg::history_log()).unwrap_or_default();
+        let user = std::env::var("SUDO_USER").ok().or(history.requested_by);
+
+        let message = mail::build_dpkg_message(&config, &changes, user, history.commandline)?;
+        let response = mail::send(&config, message)?;
+
+        if !response.is_positive() {
+            println!("Response: {:?}", response);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "collector")]
+    pub fn collector_serve(cli: &Cli, listen: &Option<String>) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let mut collector = config.collector.as_ref().cloned().unwrap_or_default();
+        if let Some(listen) = listen {
+            collector.listen = listen.clone();
+        }
+
+        collector.serve(&config)
+    }
+
+    #[cfg(feature = "collector")]
+    pub fn collector_digest(cli: &Cli) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+        let collector = config.collector.as_ref().cloned().unwrap_or_default();
+
+        let store = crate::collector::Store::open(&collector.database)?;
+        collector.send_digest(&config, &store)
+    }
+
+    pub fn ack_add(cli: &Cli, package: &str, args: &AckArgs) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let package = package.to_string();
+
+        let version = match &args.version {
+            Some(version) => version.clone(),
+            None => match apt::candidate(&package)? {
+                Some(version) => version,
+                None => {
+                    println!("{}: no candidate version", package);
+                    return Ok(());
+                }
+            },
+        };
+
+        let mut acks = ack::Acknowledgements::load(&config.state.dir())?;
+        acks.add(ack::Acknowledgement {
+            package,
+            version,
+            until: args.until,
+            reason: args.reason.clone(),
+            created: Utc::now(),
+        });
+        acks.save(&config.state.dir())?;
+
+        Ok(())
+    }
+
+    pub fn ack_list(cli: &Cli) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let today = Utc::now().date_naive();
+
+        for ack in ack::Acknowledgements::load(&config.state.dir())?.iter() {
+            let mut line = format!("{} {}", ack.package, ack.version);
+
+            if let Some(until) = ack.until {
+                line.push_str(&format!(" until {}", until));
+                if until < today {
+                    line.push_str(" (expired)");
+                }
+            }
+            if let Some(reason) = &ack.reason {
+                line.push_str(&format!(" - {}", reason));
+            }
+
+            println!("{}", line);
+        }
+
+        Ok(())
+    }
+
+    pub fn ack_remove(cli: &Cli, package: &str) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let mut acks = ack::Acknowledgements::load(&config.state.dir())?;
+
+        if acks.remove(package) {
+            acks.save(&config.state.dir())?;
+        } else {
+            println!("{}: not acknowledged", package);
+        }
+
+        Ok(())
+    }
+
+    pub fn sources_audit(cli: &Cli) -> Result<()> {
+        let config = crate::config::Config::from_file(&cli.config)?;
+
+        let findings = config.sources.run(Utc::now().date_naive())?;
+
+        if cli.output == Output::Json {
+            println!("{}", serde_json::to_string_pretty(&findings)?);
+            return Ok(());
+        }
+
+        if findings.is_empty() {
+            println!("No problems found in the apt sources");
+        }
+
+        for finding in findings {
+            println!(
+                "{}:{}: {}: {}",
+                finding.file.display(),
+                finding.line,
+                finding.source,
+                finding.message
+            );
+        }
+
+        Ok(())
+    }
 }
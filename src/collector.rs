@@ -0,0 +1,554 @@
+use crate::{
+    apt::Upgradeable,
+    config::{self, Config},
+    error::{Error, Result},
+    mail,
+    report::Report,
+};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    net::{IpAddr, ToSocketAddrs},
+    path::PathBuf,
+    thread,
+};
+use tiny_http::{Method, Request, Response, Server};
+
+/// Largest report accepted from an agent, in bytes
+const MAX_REPORT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Receives reports from agents (see [`crate::agent`]) and sends a daily digest of the fleet.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Collector {
+    /// Address the reports are received on; other than loopback addresses require a token
+    pub listen: String,
+    /// SQLite database holding the latest report of each host
+    pub database: PathBuf,
+    /// If set, agents must send it as `Authorization: Bearer <token>`, or one of `agents`.
+    /// Their reports are told apart by the agent's address.
+    pub token: Option<String>,
+    /// Tokens of individual agents, by agent name. Their reports are told apart by the name
+    /// instead of the address.
+    pub agents: BTreeMap<String, String>,
+    /// Hosts that haven't reported for this long are listed as having stopped reporting
+    pub stale_hours: i64,
+    /// Local time (`HH:MM`) the digest is sent at
+    pub digest_time: String,
+    digest_subject: String,
+    /// Number of packages listed as most widespread
+    pub top_packages: usize,
+}
+
+impl Default for Collector {
+    fn default() -> Collector {
+        Collector {
+            listen: String::from("127.0.0.1:9188"),
+            database: PathBuf::from("/var/lib/apt-updates-notifier/collector.sqlite"),
+            token: None,
+            agents: BTreeMap::new(),
+            stale_hours: 48,
+            digest_time: String::from("07:00"),
+            digest_subject: String::from("# host(s) with pending updates"),
+            top_packages: 20,
+        }
+    }
+}
+
+impl Collector {
+    pub fn digest_subject_fmt(&self, count: usize) -> String {
+        config::format_subject(&self.digest_subject, count)
+    }
+
+    fn digest_time(&self) -> Result<NaiveTime> {
+        NaiveTime::parse_from_str(&self.digest_time, "%H:%M")
+            .map_err(|err| Error::Collector(format!("digest_time: {}", err)))
+    }
+
+    /// Receives reports on `listen`, and sends the digest every day at `digest_time`.
+    pub fn serve(&self, config: &Config) -> Result<()> {
+        let digest_time = self.digest_time()?;
+        self.check_listen()?;
+
+        let server = Server::http(&self.listen)
+            .map_err(|err| Error::Collector(format!("{}: {}", self.listen, err)))?;
+        let store = Store::open(&self.database)?;
+
+        thread::scope(|scope| {
+            scope.spawn(|| loop {
+                if let Err(err) = self.send_digest_if_due(config, digest_time) {
+                    eprintln!("Digest: {}", err);
+                }
+                match config.outbox.retry(config, &config.state.dir(), false) {
+                    Ok(retried) => {
+                        for dropped in &retried.dropped {
+                            eprintln!("Outbox: dropping notification {}", dropped);
+                        }
+                        for path in &retried.quarantined {
+                            eprintln!(
+                                "Outbox: couldn't read a notification, moved to {}",
+                                path.display()
+                            );
+                        }
+                    }
+                    Err(err) => eprintln!("Outbox: {}", err),
+                }
+                thread::sleep(std::time::Duration::from_secs(60));
+            });
+
+            for request in server.incoming_requests() {
+                self.handle(&store, request);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle(&self, store: &Store, mut request: Request) {
+        let response = match (request.method(), request.url()) {
+            (Method::Post, "/reports") => self.receive(store, &mut request),
+            _ => Response::empty(404),
+        };
+
+        if let Err(err) = request.respond(response) {
+            eprintln!("Responding: {}", err);
+        }
+    }
+
+    /// Fails if `listen` accepts connections from other hosts, but no token is configured.
+    fn check_listen(&self) -> Result<()> {
+        let mut addresses = self
+            .listen
+            .to_socket_addrs()
+            .map_err(|err| Error::Collector(format!("{}: {}", self.listen, err)))?;
+
+        if self.token.is_none()
+            && self.agents.is_empty()
+            && !addresses.all(|address| address.ip().is_loopback())
+        {
+            return Err(Error::Collector(format!(
+                "{}: a token is required to receive reports from other hosts",
+                self.listen
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Who sent a request, by its `Authorization` header and address: the name of the agent
+    /// whose token it has, or the address. `None` if it isn't authorized.
+    fn identity(&self, authorization: Option<&str>, address: Option<IpAddr>) -> Option<String> {
+        let authorization = authorization.unwrap_or_default().as_bytes();
+        let has =
+            |token: &str| constant_time_eq(authorization, format!("Bearer {}", token).as_bytes());
+
+        if let Some((name, _)) = self.agents.iter().find(|(_, token)| has(token)) {
+            return Some(name.clone());
+        }
+
+        let address = address
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        match &self.token {
+            Some(token) if has(token) => Some(address),
+            None if self.agents.is_empty() => Some(address),
+            _ => None,
+        }
+    }
+
+    fn receive(&self, store: &Store, request: &mut Request) -> Response<std::io::Empty> {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str());
+
+        let Some(agent) = self.identity(authorization, request.remote_addr().map(|a| a.ip()))
+        else {
+            return Response::empty(401);
+        };
+
+        let mut body = String::new();
+        if request
+            .as_reader()
+            .take(MAX_REPORT_SIZE)
+            .read_to_string(&mut body)
+            .is_err()
+        {
+            return Response::empty(400);
+        }
+
+        let report: Report = match serde_json::from_str(&body) {
+            Ok(report) => report,
+            Err(_) => return Response::empty(400),
+        };
+
+        let host = report.host.clone().unwrap_or_else(|| agent.clone());
+
+        match store.insert(&agent, &host, Utc::now(), &body) {
+            Ok(()) => Response::empty(204),
+            Err(err) => {
+                eprintln!("Storing report from {}: {}", host, err);
+                Response::empty(500)
+            }
+        }
+    }
+
+    fn send_digest_if_due(&self, config: &Config, digest_time: NaiveTime) -> Result<()> {
+        let now = Local::now();
+        let store = Store::open(&self.database)?;
+
+        if now.time() < digest_time || store.last_digest()? == Some(now.date_naive()) {
+            return Ok(());
+        }
+
+        let sent = self.send_digest(config, &store);
+        record_digest(&store, now.date_naive(), sent)
+    }
+
+    /// Sends the digest of the latest reports stored in `database`.
+    pub fn send_digest(&self, config: &Config, store: &Store) -> Result<()> {
+        let digest = Digest::new(store.hosts()?, self.stale_hours, self.top_packages);
+
+        if digest.hosts.is_empty() && digest.stale.is_empty() {
+            return Ok(());
+        }
+
+        let message = mail::build_digest_message(config, self, &digest)?;
+        let response = mail::send(config, message)?;
+
+        if !response.is_positive() {
+            println!("Response: {:?}", response);
+        }
+
+        Ok(())
+    }
+}
+
+/// Records that the digest of `date` was sent, also if it was kept in the outbox to be
+/// retried from there, so that it isn't built and kept in the outbox again every minute.
+fn record_digest(store: &Store, date: NaiveDate, sent: Result<()>) -> Result<()> {
+    if sent.as_ref().is_ok() || sent.as_ref().is_err_and(Error::is_kept_in_outbox) {
+        store.set_last_digest(date)?;
+    }
+
+    sent
+}
+
+/// Compares without returning early, so that the time taken doesn't reveal how much of a
+/// token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= usize::from(x ^ y);
+    }
+
+    diff == 0
+}
+
+/// The latest report received from a host.
+pub struct Host {
+    pub name: String,
+    /// The agent that sent the report: the name of its token in `agents`, or its address
+    pub agent: String,
+    pub received: DateTime<Utc>,
+    pub report: Report,
+}
+
+impl Host {
+    /// e.g. `web1 (192.0.2.10)`, or only the name if the agent has the same name.
+    pub fn label(&self) -> String {
+        if self.name == self.agent {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.agent)
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.report.unacknowledged().count()
+    }
+
+    pub fn security(&self) -> usize {
+        self.report
+            .unacknowledged()
+            .filter(|pkg| pkg.is_security())
+            .count()
+    }
+}
+
+pub struct Store(Connection);
+
+impl Store {
+    pub fn open(path: &std::path::Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let connection = Connection::open(path)?;
+        // Reports used to be stored by host name only, which agents could overwrite each
+        // other's; they are sent again on the agents' next run
+        connection.execute_batch(
+            "DROP TABLE IF EXISTS hosts;
+            CREATE TABLE IF NOT EXISTS reports (
+                agent TEXT NOT NULL,
+                host TEXT NOT NULL,
+                received TEXT NOT NULL,
+                report TEXT NOT NULL,
+                PRIMARY KEY (agent, host)
+            );
+            CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(Self(connection))
+    }
+
+    /// Stores `report` as the latest one from `host`, sent by `agent`.
+    pub fn insert(
+        &self,
+        agent: &str,
+        host: &str,
+        received: DateTime<Utc>,
+        report: &str,
+    ) -> Result<()> {
+        self.0.execute(
+            "INSERT INTO reports (agent, host, received, report) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (agent, host) DO UPDATE SET received = ?3, report = ?4",
+            params![agent, host, received.to_rfc3339(), report],
+        )?;
+
+        Ok(())
+    }
+
+    /// The latest report of each host, sorted by host and agent.
+    pub fn hosts(&self) -> Result<Vec<Host>> {
+        let mut statement = self
+            .0
+            .prepare("SELECT host, agent, received, report FROM reports ORDER BY host, agent")?;
+
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut hosts = Vec::new();
+        for row in rows {
+            let (name, agent, received, report) = row?;
+
+            let received = DateTime::parse_from_rfc3339(&received)
+                .map_err(|err| Error::Collector(format!("{}: {}", name, err)))?
+                .with_timezone(&Utc);
+
+            hosts.push(Host {
+                name,
+                agent,
+                received,
+                report: serde_json::from_str(&report)?,
+            });
+        }
+
+        Ok(hosts)
+    }
+
+    pub fn last_digest(&self) -> Result<Option<chrono::NaiveDate>> {
+        let value: Option<String> = self
+            .0
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'last_digest'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(value.and_then(|date| date.parse().ok()))
+    }
+
+    pub fn set_last_digest(&self, date: chrono::NaiveDate) -> Result<()> {
+        self.0.execute(
+            "INSERT INTO meta (key, value) VALUES ('last_digest', ?1)
+             ON CONFLICT (key) DO UPDATE SET value = ?1",
+            params![date.to_string()],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Summary of the fleet, sent by email.
+pub struct Digest {
+    pub timestamp: DateTime<Utc>,
+    /// Hosts that reported recently
+    pub hosts: Vec<Host>,
+    /// Hosts that stopped reporting
+    pub stale: Vec<Host>,
+    top_packages: usize,
+}
+
+impl Digest {
+    pub fn new(hosts: Vec<Host>, stale_hours: i64, top_packages: usize) -> Self {
+        let timestamp = Utc::now();
+        let (stale, hosts) = hosts
+            .into_iter()
+            .partition(|host| timestamp - host.received > Duration::hours(stale_hours));
+
+        Self {
+            timestamp,
+            hosts,
+            stale,
+            top_packages,
+        }
+    }
+
+    /// Hosts with unacknowledged updates.
+    pub fn pending_hosts(&self) -> usize {
+        self.hosts.iter().filter(|host| host.pending() > 0).count()
+    }
+
+    /// The unacknowledged updates pending on the most hosts, with the number of hosts and
+    /// one of the reports listing it.
+    pub fn widespread(&self) -> Vec<(usize, &Upgradeable, &Report)> {
+        let mut updates: BTreeMap<(&str, Option<&str>), (usize, &Upgradeable, &Report)> =
+            BTreeMap::new();
+
+        for host in &self.hosts {
+            for pkg in host.report.unacknowledged() {
+                updates
+                    .entry((&pkg.name, pkg.candidate.as_deref()))
+                    .or_insert((0, pkg, &host.report))
+                    .0 += 1;
+            }
+        }
+
+        let mut updates: Vec<(usize, &Upgradeable, &Report)> = updates.into_values().collect();
+        updates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.name.cmp(&b.1.name)));
+        updates.truncate(self.top_packages);
+        updates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secreT", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret2", b"Bearer secret"));
+        assert!(!constant_time_eq(b"", b"Bearer secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn identity_of_agents() {
+        let address: Option<IpAddr> = Some("192.0.2.10".parse().unwrap());
+        let mut collector = Collector::default();
+        assert_eq!(
+            collector.identity(None, address).as_deref(),
+            Some("192.0.2.10")
+        );
+
+        collector.token = Some(String::from("shared"));
+        collector
+            .agents
+            .insert(String::from("web1"), String::from("secret1"));
+
+        assert_eq!(
+            collector
+                .identity(Some("Bearer secret1"), address)
+                .as_deref(),
+            Some("web1")
+        );
+        assert_eq!(
+            collector
+                .identity(Some("Bearer shared"), address)
+                .as_deref(),
+            Some("192.0.2.10")
+        );
+        assert_eq!(collector.identity(Some("Bearer secret2"), address), None);
+        assert_eq!(collector.identity(None, address), None);
+
+        collector.token = None;
+        assert_eq!(collector.identity(Some("Bearer shared"), address), None);
+    }
+
+    #[test]
+    fn only_local_addresses_without_a_token() {
+        let mut collector = Collector::default();
+        assert!(collector.check_listen().is_ok());
+
+        collector.listen = String::from("0.0.0.0:9188");
+        assert!(collector.check_listen().is_err());
+
+        collector.token = Some(String::from("secret"));
+        assert!(collector.check_listen().is_ok());
+    }
+
+    #[test]
+    fn digest_kept_in_the_outbox_is_recorded() {
+        let database = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-collector-{}-digest.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&database);
+        let store = Store::open(&database).unwrap();
+        let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+        let smtp_error = || Error::Collector(String::from("connection refused"));
+
+        let unsent = Error::Unsent(Box::new(smtp_error()), None);
+        assert!(record_digest(&store, day(1), Err(unsent)).is_err());
+        assert_eq!(store.last_digest().unwrap(), Some(day(1)));
+
+        // Lost: built and sent again on the next attempt
+        let lost = Error::Unsent(Box::new(smtp_error()), Some(Box::new(smtp_error())));
+        assert!(record_digest(&store, day(2), Err(lost)).is_err());
+        assert!(record_digest(&store, day(2), Err(smtp_error())).is_err());
+        assert_eq!(store.last_digest().unwrap(), Some(day(1)));
+
+        record_digest(&store, day(2), Ok(())).unwrap();
+        assert_eq!(store.last_digest().unwrap(), Some(day(2)));
+
+        std::fs::remove_file(database).unwrap();
+    }
+
+    #[test]
+    fn hosts_of_different_agents_do_not_collide() {
+        let database = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-collector-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&database);
+        let store = Store::open(&database).unwrap();
+        let report = |host: &str| {
+            let report = Report {
+                host: Some(host.to_string()),
+                ..Default::default()
+            };
+            report.to_json().unwrap()
+        };
+        let now = Utc::now();
+
+        store.insert("web1", "db", now, &report("db")).unwrap();
+        store.insert("web2", "db", now, &report("db")).unwrap();
+        store.insert("web1", "db", now, &report("db")).unwrap();
+        store.insert("web1", "web1", now, &report("web1")).unwrap();
+
+        let labels: Vec<String> = store.hosts().unwrap().iter().map(Host::label).collect();
+        assert_eq!(labels, ["db (web1)", "db (web2)", "web1"]);
+
+        std::fs::remove_file(database).unwrap();
+    }
+}
@@ -1,12 +1,37 @@
 use std::{
     fs::OpenOptions,
     io::{BufWriter, Write},
+    path::PathBuf,
 };
 
 use crate::{
+    agent::Agent,
+    cve::Feed,
     error::{Error, Result},
-    util,
+    filter::Filter,
+    news::Sources,
+    repository::Repositories,
+    schedule::Schedule,
+    sources::Audit,
+    summary::Summary,
+    target::Target,
 };
+use crate::util;
+#[cfg(feature = "mail")]
+use crate::apt::Upgradeable;
+#[cfg(feature = "mail")]
+use crate::failure::OnError;
+#[cfg(feature = "mail")]
+use crate::outbox::Outbox;
+#[cfg(feature = "collector")]
+use crate::collector::Collector;
+#[cfg(feature = "daemon")]
+use crate::daemon::Daemon;
+#[cfg(feature = "serve")]
+use crate::status::Status;
+#[cfg(feature = "mail")]
+use chrono::{DateTime, Utc};
+#[cfg(feature = "mail")]
 use lettre::{
     message::{header, Mailbox, Mailboxes},
     transport::smtp,
@@ -15,8 +40,48 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct Config {
+    #[cfg(feature = "mail")]
     pub smtp: SMTP,
+    #[cfg(feature = "mail")]
     pub mail: Mail,
+    /// Retrying notifications that couldn't be sent
+    #[cfg(feature = "mail")]
+    #[serde(default)]
+    pub outbox: Outbox,
+    /// Notifying about failures of the check itself
+    #[cfg(feature = "mail")]
+    pub on_error: Option<OnError>,
+    #[serde(default)]
+    pub state: State,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub ack: Ack,
+    pub cve: Option<Feed>,
+    #[serde(default)]
+    pub news: Sources,
+    #[serde(default)]
+    pub repositories: Repositories,
+    /// Auditing the apt sources
+    #[serde(default)]
+    pub sources: Audit,
+    /// Quiet hours
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Send a weekly or monthly summary instead of a notification per run
+    pub summary: Option<Summary>,
+    /// Systems to check in one run, sent as a single combined notification
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    pub agent: Option<Agent>,
+    #[cfg(feature = "collector")]
+    pub collector: Option<Collector>,
+    #[cfg(feature = "serve")]
+    #[serde(default)]
+    pub serve: Status,
+    #[cfg(feature = "daemon")]
+    #[serde(default)]
+    pub daemon: Daemon,
 }
 
 impl Config {
@@ -25,7 +90,28 @@ impl Config {
             .add_source(config::File::with_name(file).format(config::FileFormat::Toml))
             .build()?;
 
-        Ok(config.try_deserialize()?)
+        let config: Config = config.try_deserialize()?;
+        config.filter.validate()?;
+
+        Ok(config)
+    }
+
+    /// Loads only the sections needed to send notifications, for when the file as a whole
+    /// can't be loaded, so that the failure can still be notified about.
+    #[cfg(feature = "mail")]
+    pub fn notification_sections(file: &str) -> Result<Config> {
+        let config = config::Config::builder()
+            .add_source(config::File::with_name(file).format(config::FileFormat::Toml))
+            .build()?;
+
+        Ok(Config {
+            smtp: config.get("smtp")?,
+            mail: config.get("mail")?,
+            state: config.get("state").unwrap_or_default(),
+            outbox: config.get("outbox").unwrap_or_default(),
+            on_error: config.get("on_error").ok(),
+            ..Default::default()
+        })
     }
 
     pub fn to_file(&self, file: &str) -> Result<()> {
@@ -47,6 +133,7 @@ impl Config {
     }
 }
 
+#[cfg(feature = "mail")]
 #[derive(Default, Deserialize, Serialize)]
 pub struct SMTP {
     relay: String,
@@ -54,6 +141,7 @@ pub struct SMTP {
     auth_password: String,
 }
 
+#[cfg(feature = "mail")]
 impl SMTP {
     pub fn new(relay: String, auth_username: String, auth_password: String) -> Self {
         Self {
@@ -80,17 +168,37 @@ impl SMTP {
     }
 }
 
+#[cfg(feature = "mail")]
 #[derive(Deserialize, Serialize)]
 #[serde(default)]
 pub struct Mail {
     pub from: String,
     to: Vec<Mailbox>,
     subject: String,
+    dpkg_subject: String,
     html: bool,
     prepend: Option<String>,
     append: Option<String>,
+    group_by: GroupBy,
+    /// Packages shown per group in HTML, the others are collapsed
+    group_limit: usize,
+    escalation: Vec<Escalation>,
+}
+
+/// How packages are grouped in notifications.
+#[cfg(feature = "mail")]
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    #[default]
+    None,
+    /// Binary packages built from the same source package
+    Source,
+    Origin,
+    Section,
 }
 
+#[cfg(feature = "mail")]
 impl Mail {
     pub fn new(from: String, to: Vec<Mailbox>, subject: String) -> Self {
         Self {
@@ -114,10 +222,11 @@ impl Mail {
         self.to.clone().into()
     }
     pub fn subject_fmt(&self, count: usize) -> String {
-        self.subject
-            .clone()
-            .replace('#', &count.to_string())
-            .replace('@', &util::get_hostname().unwrap_or("".to_string()))
+        format_subject(&self.subject, count)
+    }
+
+    pub fn dpkg_subject_fmt(&self, count: usize) -> String {
+        format_subject(&self.dpkg_subject, count)
     }
 
     pub fn subject(&self) -> String {
@@ -136,17 +245,123 @@ impl Mail {
     pub fn append(&self) -> Option<String> {
         self.append.clone()
     }
+    pub fn group_by(&self) -> GroupBy {
+        self.group_by
+    }
+    pub fn group_limit(&self) -> usize {
+        self.group_limit
+    }
+
+    /// Escalation rules matched by at least one of the packages.
+    pub fn escalations(&self, packages: &[&Upgradeable], now: DateTime<Utc>) -> Vec<&Escalation> {
+        self.escalation
+            .iter()
+            .filter(|rule| packages.iter().any(|pkg| rule.matches(pkg, now)))
+            .collect()
+    }
 }
 
+#[cfg(feature = "mail")]
 impl Default for Mail {
     fn default() -> Mail {
         Mail {
             subject: "There are # package updates available for @".to_string(),
+            dpkg_subject: "# packages upgraded/installed/removed on @".to_string(),
             html: true,
             from: "".to_string(),
             to: Vec::new(),
             prepend: None,
             append: None,
+            group_by: GroupBy::None,
+            group_limit: 5,
+            escalation: Vec::new(),
+        }
+    }
+}
+
+/// Sends the notification to more recipients once an update has been pending for too long.
+#[cfg(feature = "mail")]
+#[derive(Deserialize, Serialize)]
+pub struct Escalation {
+    /// Only applies to security updates
+    #[serde(default)]
+    security: bool,
+    after_days: i64,
+    /// Additional recipients (Cc)
+    #[serde(default)]
+    to: Vec<Mailbox>,
+    /// Mark the message as high priority
+    #[serde(default = "default_high_priority")]
+    high_priority: bool,
+}
+
+#[cfg(feature = "mail")]
+fn default_high_priority() -> bool {
+    true
+}
+
+#[cfg(feature = "mail")]
+impl Escalation {
+    pub fn matches(&self, pkg: &Upgradeable, now: DateTime<Utc>) -> bool {
+        (!self.security || pkg.is_security())
+            && pkg
+                .pending_days(now)
+                .is_some_and(|days| days >= self.after_days)
+    }
+
+    pub fn to(&self) -> Vec<Mailbox> {
+        self.to.clone()
+    }
+
+    pub fn high_priority(&self) -> bool {
+        self.high_priority
+    }
+}
+
+#[cfg(feature = "mail")]
+pub(crate) fn format_subject(subject: &str, count: usize) -> String {
+    subject
+        .replace('#', &count.to_string())
+        .replace('@', &util::host_label().unwrap_or("".to_string()))
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Ack {
+    /// Leave acknowledged updates out of notifications instead of showing them greyed out
+    hide: bool,
+}
+
+impl Ack {
+    pub fn hide(&self) -> bool {
+        self.hide
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    dir: PathBuf,
+}
+
+impl State {
+    /// The state directory, or a subdirectory of it when apt is pointed at another root
+    /// (see [`crate::apt::set_root`]), so that roots don't share pending updates and acknowledgements.
+    pub fn dir(&self) -> PathBuf {
+        match util::root() {
+            Some(root) => self
+                .dir
+                .join("roots")
+                .join(root.to_string_lossy().trim_matches('/').replace('/', "_")),
+            None => self.dir.clone(),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            dir: PathBuf::from("/var/lib/apt-updates-notifier"),
         }
     }
 }
@@ -0,0 +1,388 @@
+use crate::{
+    apt::Upgradeable,
+    error::{Error, Result},
+    util,
+};
+use rust_apt::util::cmp_versions;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// Security tracker data used to list the CVEs fixed by each update.
+#[derive(Deserialize, Serialize)]
+pub struct Feed {
+    pub format: Format,
+    /// Local copy of the data
+    pub path: PathBuf,
+    /// If set, `path` is downloaded from here when it is older than `max_age_hours`
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default = "default_max_age_hours")]
+    pub max_age_hours: u64,
+    /// Sort packages by the highest severity CVE they fix
+    #[serde(default)]
+    pub sort: bool,
+    /// Add the number of CVEs fixed to the subject
+    #[serde(default)]
+    pub subject: bool,
+}
+
+fn default_max_age_hours() -> u64 {
+    24
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// <https://security-tracker.debian.org/tracker/data/json>
+    Debian,
+    /// Ubuntu Security Notices database (`database.json`), which has no severities. Ubuntu's
+    /// OVAL data isn't supported.
+    UbuntuUsn,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Unknown,
+    Unimportant,
+    Low,
+    Medium,
+    High,
+}
+
+impl From<&str> for Severity {
+    fn from(urgency: &str) -> Self {
+        // Debian urgencies may be followed by "*" or "**" when not yet confirmed
+        match urgency.trim_end_matches('*') {
+            "unimportant" => Self::Unimportant,
+            "low" => Self::Low,
+            "medium" => Self::Medium,
+            "high" => Self::High,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown"),
+            Self::Unimportant => write!(f, "unimportant"),
+            Self::Low => write!(f, "low"),
+            Self::Medium => write!(f, "medium"),
+            Self::High => write!(f, "high"),
+        }
+    }
+}
+
+/// A CVE fixed by an update.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cve {
+    pub id: String,
+    pub severity: Severity,
+}
+
+struct Fix {
+    id: String,
+    fixed_version: String,
+    severity: Severity,
+}
+
+/// Fixes for this release, keyed by source package.
+pub struct Database(HashMap<String, Vec<Fix>>);
+
+#[derive(Deserialize)]
+struct DebianCve {
+    #[serde(default)]
+    releases: HashMap<String, DebianRelease>,
+}
+
+#[derive(Deserialize)]
+struct DebianRelease {
+    fixed_version: Option<String>,
+    urgency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usn {
+    #[serde(default)]
+    cves: Vec<String>,
+    #[serde(default)]
+    releases: HashMap<String, UsnRelease>,
+}
+
+#[derive(Deserialize)]
+struct UsnRelease {
+    #[serde(default)]
+    sources: HashMap<String, UsnSource>,
+}
+
+#[derive(Deserialize)]
+struct UsnSource {
+    version: String,
+}
+
+impl Feed {
+    /// Loads the fixes for the host's release (`VERSION_CODENAME` in `/etc/os-release`, inside the root if one is set).
+    ///
+    /// Call [`Feed::refresh`] first to download the data.
+    pub fn load(&self) -> Result<Database> {
+        let codename = util::read_os_release(util::in_root("/etc/os-release"))
+            .get("VERSION_CODENAME")
+            .cloned()
+            .unwrap_or_default();
+
+        self.parse(&fs::read_to_string(&self.path)?, &codename)
+    }
+
+    fn parse(&self, contents: &str, codename: &str) -> Result<Database> {
+        let mut fixes: HashMap<String, Vec<Fix>> = HashMap::new();
+
+        match self.format {
+            Format::Debian => {
+                let data: HashMap<String, HashMap<String, DebianCve>> =
+                    serde_json::from_str(contents)?;
+
+                for (source, cves) in data {
+                    for (id, cve) in cves {
+                        let Some(release) = cve.releases.get(codename) else {
+                            continue;
+                        };
+                        let Some(fixed_version) = &release.fixed_version else {
+                            continue;
+                        };
+
+                        fixes.entry(source.clone()).or_default().push(Fix {
+                            id,
+                            fixed_version: fixed_version.clone(),
+                            severity: release.urgency.as_deref().unwrap_or_default().into(),
+                        });
+                    }
+                }
+            }
+            Format::UbuntuUsn => {
+                let data: HashMap<String, Usn> = serde_json::from_str(contents)?;
+
+                for usn in data.into_values() {
+                    let Some(release) = usn.releases.get(codename) else {
+                        continue;
+                    };
+
+                    for (source, fixed) in &release.sources {
+                        fixes
+                            .entry(source.clone())
+                            .or_default()
+                            .extend(usn.cves.iter().map(|id| Fix {
+                                id: id.clone(),
+                                fixed_version: fixed.version.clone(),
+                                severity: Severity::Unknown,
+                            }));
+                    }
+                }
+            }
+        }
+
+        Ok(Database(fixes))
+    }
+
+    /// Downloads the data if `url` is set and the local copy is missing or too old.
+    ///
+    /// If the download fails, the local copy (if any) is left as it is.
+    pub fn refresh(&self) -> Result<()> {
+        let Some(url) = &self.url else {
+            return Ok(());
+        };
+
+        let fresh = fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < Duration::from_secs(self.max_age_hours * 3600));
+
+        if fresh {
+            return Ok(());
+        }
+
+        download(url, &self.path)
+    }
+}
+
+#[cfg(feature = "http")]
+fn download(url: &str, path: &std::path::Path) -> Result<()> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|err| Error::Http(err.to_string()))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    std::io::copy(&mut response.into_reader(), &mut fs::File::create(&tmp)?)?;
+    fs::rename(tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn download(_url: &str, _path: &std::path::Path) -> Result<()> {
+    Err(Error::Http(String::from(
+        "built without the `http` feature",
+    )))
+}
+
+impl Database {
+    /// Sets `cves` on each package to the CVEs fixed between the installed and the candidate version.
+    pub fn enrich(&self, packages: &mut [Upgradeable]) {
+        for pkg in packages.iter_mut() {
+            let (Some(installed), Some(fixes)) = (&pkg.installed, self.0.get(&pkg.source_name))
+            else {
+                continue;
+            };
+
+            pkg.cves = fixes
+                .iter()
+                .filter(|fix| {
+                    cmp_versions(installed, &fix.fixed_version) == Ordering::Less
+                        && cmp_versions(&fix.fixed_version, &pkg.source_version)
+                            != Ordering::Greater
+                })
+                .map(|fix| Cve {
+                    id: fix.id.clone(),
+                    severity: fix.severity,
+                })
+                .collect();
+
+            pkg.cves.sort_by(|a, b| a.id.cmp(&b.id));
+            pkg.cves.dedup_by(|a, b| a.id == b.id);
+            pkg.cves.sort_by_key(|cve| Reverse(cve.severity));
+        }
+    }
+}
+
+/// Sorts packages by the highest severity CVE they fix, then by name.
+pub fn sort_by_severity(packages: &mut [Upgradeable]) {
+    packages.sort_by(|a, b| {
+        b.max_severity()
+            .cmp(&a.max_severity())
+            .then(a.name.cmp(&b.name))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(format: Format) -> Feed {
+        Feed {
+            format,
+            path: PathBuf::new(),
+            url: None,
+            max_age_hours: default_max_age_hours(),
+            sort: false,
+            subject: false,
+        }
+    }
+
+    fn upgradeable(installed: &str, candidate: &str) -> Upgradeable {
+        Upgradeable {
+            name: String::from("libssl3"),
+            installed: Some(installed.to_string()),
+            candidate: Some(candidate.to_string()),
+            source_name: String::from("openssl"),
+            source_version: candidate.to_string(),
+            ..Default::default()
+        }
+    }
+
+    const DEBIAN: &str = r#"{
+        "openssl": {
+            "CVE-2024-0001": {
+                "releases": {
+                    "bookworm": { "fixed_version": "3.0.13-1~deb12u1", "urgency": "high" },
+                    "bullseye": { "fixed_version": "1.1.1w-0+deb11u2", "urgency": "high" }
+                }
+            },
+            "CVE-2024-0002": {
+                "releases": { "bookworm": { "fixed_version": "3.0.11-1~deb12u2", "urgency": "low*" } }
+            },
+            "CVE-2024-0003": {
+                "releases": { "bookworm": { "fixed_version": "3.0.14-1~deb12u1", "urgency": "medium" } }
+            },
+            "CVE-2024-0004": {
+                "releases": { "bookworm": { "urgency": "unimportant" } }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn debian_fixes_between_installed_and_candidate() {
+        let database = feed(Format::Debian).parse(DEBIAN, "bookworm").unwrap();
+        let mut packages = [upgradeable("3.0.11-1~deb12u2", "3.0.13-1~deb12u1")];
+
+        database.enrich(&mut packages);
+
+        let cves: Vec<(&str, Severity)> = packages[0]
+            .cves
+            .iter()
+            .map(|cve| (cve.id.as_str(), cve.severity))
+            .collect();
+        assert_eq!(cves, vec![("CVE-2024-0001", Severity::High)]);
+    }
+
+    #[test]
+    fn ubuntu_usn() {
+        let usn = r#"{
+            "6754-1": {
+                "cves": ["CVE-2024-0001", "CVE-2024-0002"],
+                "releases": { "jammy": { "sources": { "openssl": { "version": "3.0.2-0ubuntu1.15" } } } }
+            }
+        }"#;
+        let database = feed(Format::UbuntuUsn).parse(usn, "jammy").unwrap();
+        let mut packages = [upgradeable("3.0.2-0ubuntu1.14", "3.0.2-0ubuntu1.15")];
+
+        database.enrich(&mut packages);
+
+        assert_eq!(packages[0].cves.len(), 2);
+        assert!(packages[0]
+            .cves
+            .iter()
+            .all(|cve| cve.severity == Severity::Unknown));
+    }
+
+    #[test]
+    fn severity_from_urgency() {
+        assert_eq!(Severity::from("high**"), Severity::High);
+        assert_eq!(Severity::from("not yet assigned"), Severity::Unknown);
+    }
+
+    #[test]
+    fn sort_by_highest_severity() {
+        let mut low = upgradeable("1", "2");
+        low.name = String::from("b");
+        low.cves = vec![Cve {
+            id: String::from("CVE-1"),
+            severity: Severity::Low,
+        }];
+        let mut high = upgradeable("1", "2");
+        high.name = String::from("c");
+        high.cves = vec![Cve {
+            id: String::from("CVE-2"),
+            severity: Severity::High,
+        }];
+        let mut none = upgradeable("1", "2");
+        none.name = String::from("a");
+
+        let mut packages = [none, low, high];
+        sort_by_severity(&mut packages);
+
+        let names: Vec<&str> = packages.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(names, vec!["c", "b", "a"]);
+    }
+}
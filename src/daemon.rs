@@ -0,0 +1,255 @@
+use crate::{
+    error::{Error, Result},
+    util,
+};
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{os::fd::AsRawFd, str::FromStr, thread, time::Duration};
+
+const APT_LISTS: &str = "/var/lib/apt/lists";
+const DPKG_DIR: &str = "/var/lib/dpkg";
+
+/// Runs checks on a schedule and when the apt state changes, for systems without systemd timers.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Daemon {
+    /// Cron expression, with or without a leading seconds field (e.g. `0 7 * * *`), in local time
+    pub schedule: String,
+    /// Scheduled checks are delayed by up to this many minutes
+    pub jitter_minutes: u64,
+    /// Check when the package lists or the dpkg status change
+    pub watch: bool,
+    /// Wait until there have been no changes for this long before checking
+    pub debounce_secs: u64,
+}
+
+impl Default for Daemon {
+    fn default() -> Daemon {
+        Daemon {
+            schedule: String::from("0 7 * * *"),
+            jitter_minutes: 30,
+            watch: true,
+            debounce_secs: 30,
+        }
+    }
+}
+
+/// Why a check is run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    Schedule,
+    /// The package lists or the dpkg status changed
+    Changed,
+    /// The time returned by `wake` was reached
+    Wake,
+}
+
+impl Daemon {
+    /// Calls `check` at the scheduled times, after changes and at the time returned by `wake`
+    /// (if earlier than the next scheduled one), until an error occurs setting up the watches.
+    ///
+    /// The jittered scheduled time is kept until it is reached, so that checks after changes
+    /// or at `wake` don't postpone the scheduled one.
+    pub fn run<W, F>(&self, wake: W, mut check: F) -> Result<()>
+    where
+        W: Fn() -> Option<DateTime<Local>>,
+        F: FnMut(Trigger),
+    {
+        let schedule = self.schedule()?;
+        let inotify = if self.watch {
+            Some(self.watch()?)
+        } else {
+            None
+        };
+
+        let mut scheduled = self.next(&schedule)?;
+
+        loop {
+            let (next, at_next) = earliest(scheduled, wake());
+
+            let trigger = match inotify {
+                Some(inotify) => wait(inotify, next, at_next, self.debounce_secs)?,
+                None => {
+                    thread::sleep(until(next));
+                    at_next
+                }
+            };
+
+            check(trigger);
+
+            if trigger == Trigger::Schedule {
+                scheduled = self.next(&schedule)?;
+            }
+
+            // Ignore the changes made by the check itself (e.g. updating the package lists)
+            if let Some(inotify) = inotify {
+                while inotify.read_events().is_ok() {}
+            }
+        }
+    }
+
+    fn schedule(&self) -> Result<Schedule> {
+        let expression = match self.schedule.split_whitespace().count() {
+            5 => format!("0 {}", self.schedule),
+            _ => self.schedule.clone(),
+        };
+
+        Schedule::from_str(&expression)
+            .map_err(|err| Error::Daemon(format!("{}: {}", self.schedule, err)))
+    }
+
+    /// The next scheduled time, plus jitter.
+    fn next(&self, schedule: &Schedule) -> Result<DateTime<Local>> {
+        let next = schedule
+            .upcoming(Local)
+            .next()
+            .ok_or_else(|| Error::Daemon(format!("{}: no upcoming time", self.schedule)))?;
+
+        let jitter = rand::thread_rng().gen_range(0..=self.jitter_minutes * 60);
+
+        Ok(next + chrono::Duration::seconds(jitter as i64))
+    }
+
+    fn watch(&self) -> Result<Inotify> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(std::io::Error::from)?;
+
+        // Files are replaced by renaming, e.g. `status-new` to `status`
+        let flags =
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE;
+
+        for dir in [APT_LISTS, DPKG_DIR] {
+            inotify
+                .add_watch(&util::in_root(dir), flags)
+                .map_err(|err| Error::Daemon(format!("{}: {}", dir, err)))?;
+        }
+
+        Ok(inotify)
+    }
+}
+
+/// The scheduled time, or `wake` if it is earlier.
+fn earliest(
+    scheduled: DateTime<Local>,
+    wake: Option<DateTime<Local>>,
+) -> (DateTime<Local>, Trigger) {
+    match wake {
+        Some(wake) if wake < scheduled => (wake, Trigger::Wake),
+        _ => (scheduled, Trigger::Schedule),
+    }
+}
+
+/// Waits until `next`, returning `at_next`, or until a relevant change is followed by
+/// `debounce_secs` without changes.
+fn wait(
+    inotify: Inotify,
+    next: DateTime<Local>,
+    at_next: Trigger,
+    debounce_secs: u64,
+) -> Result<Trigger> {
+    let mut changed = false;
+
+    loop {
+        let timeout = if changed {
+            Duration::from_secs(debounce_secs)
+        } else {
+            until(next)
+        };
+
+        if !changed && timeout.is_zero() {
+            return Ok(at_next);
+        }
+
+        let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+        match poll(&mut fds, timeout_ms) {
+            Ok(0) if changed => return Ok(Trigger::Changed),
+            Ok(0) => continue,
+            Ok(_) => {
+                let events = inotify.read_events().unwrap_or_default();
+                changed |= events.iter().any(|event| {
+                    event.name.as_ref().is_some_and(|name| {
+                        let name = name.to_string_lossy();
+                        // apt's lock and temporary files, and dpkg's other files
+                        name != "lock"
+                            && name != "partial"
+                            && !name.starts_with("lock-")
+                            && (!name.ends_with("-new") && !name.ends_with("-old"))
+                    })
+                });
+            }
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(err) => return Err(std::io::Error::from(err).into()),
+        }
+    }
+}
+
+fn until(time: DateTime<Local>) -> Duration {
+    (time - Local::now()).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn schedule_with_or_without_seconds() {
+        let daemon = Daemon {
+            jitter_minutes: 0,
+            ..Default::default()
+        };
+        let next = daemon.next(&daemon.schedule().unwrap()).unwrap();
+        assert_eq!(next.format("%H:%M:%S").to_string(), "07:00:00");
+
+        let daemon = Daemon {
+            schedule: String::from("30 15 6 * * *"),
+            ..daemon
+        };
+        let next = daemon.next(&daemon.schedule().unwrap()).unwrap();
+        assert_eq!(next.format("%H:%M:%S").to_string(), "06:15:30");
+
+        let daemon = Daemon {
+            schedule: String::from("every day"),
+            ..daemon
+        };
+        assert!(daemon.schedule().is_err());
+    }
+
+    #[test]
+    fn jitter_is_bounded() {
+        let daemon = Daemon::default();
+        let schedule = daemon.schedule().unwrap();
+        let upcoming = schedule.upcoming(Local).next().unwrap();
+
+        for _ in 0..20 {
+            let next = daemon.next(&schedule).unwrap();
+            assert!(next >= upcoming);
+            assert!(next <= upcoming + chrono::Duration::minutes(30));
+        }
+    }
+
+    #[test]
+    fn wake_only_when_earlier() {
+        let scheduled = Local.with_ymd_and_hms(2024, 5, 2, 7, 12, 0).unwrap();
+        let quiet_end = Local.with_ymd_and_hms(2024, 5, 2, 6, 0, 0).unwrap();
+        let retry = Local.with_ymd_and_hms(2024, 5, 2, 8, 0, 0).unwrap();
+
+        assert_eq!(
+            earliest(scheduled, Some(quiet_end)),
+            (quiet_end, Trigger::Wake)
+        );
+        assert_eq!(
+            earliest(scheduled, Some(retry)),
+            (scheduled, Trigger::Schedule)
+        );
+        assert_eq!(earliest(scheduled, None), (scheduled, Trigger::Schedule));
+    }
+}
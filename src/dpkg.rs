@@ -0,0 +1,319 @@
+use crate::{error::Result, util};
+use rust_apt::util::cmp_versions;
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+pub const STATUS_FILE: &str = "/var/lib/dpkg/status";
+pub const HISTORY_LOG: &str = "/var/log/apt/history.log";
+
+/// The dpkg status file, inside the root set with [`crate::apt::set_root`] if any.
+pub fn status_file() -> PathBuf {
+    util::in_root(STATUS_FILE)
+}
+
+/// The apt history log, inside the root set with [`crate::apt::set_root`] if any.
+pub fn history_log() -> PathBuf {
+    util::in_root(HISTORY_LOG)
+}
+
+/// Installed packages keyed by `name:arch`, mapped to their version.
+pub type Status = BTreeMap<String, String>;
+
+/// Reads the installed packages from a dpkg status file.
+pub fn read_status<P: AsRef<Path>>(path: P) -> Result<Status> {
+    Ok(parse_status(&fs::read_to_string(path)?))
+}
+
+fn parse_status(contents: &str) -> Status {
+    let mut status = Status::new();
+
+    for stanza in contents.split("\n\n") {
+        let mut name = None;
+        let mut arch = None;
+        let mut version = None;
+        let mut installed = false;
+
+        for line in stanza.lines() {
+            if let Some(value) = line.strip_prefix("Package:") {
+                name = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Architecture:") {
+                arch = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Version:") {
+                version = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("Status:") {
+                // "want flag status", e.g. "install ok installed"
+                installed = value.split_whitespace().nth(2) == Some("installed");
+            }
+        }
+
+        if let (true, Some(name), Some(version)) = (installed, name, version) {
+            let key = match arch {
+                Some(arch) => format!("{name}:{arch}"),
+                None => name.to_string(),
+            };
+            status.insert(key, version.to_string());
+        }
+    }
+
+    status
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    Installed,
+    Upgraded,
+    Downgraded,
+    Removed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Installed => write!(f, "Installed"),
+            Self::Upgraded => write!(f, "Upgraded"),
+            Self::Downgraded => write!(f, "Downgraded"),
+            Self::Removed => write!(f, "Removed"),
+        }
+    }
+}
+
+pub struct Change {
+    pub name: String,
+    pub kind: ChangeKind,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Compares two status snapshots and returns the packages that changed.
+///
+/// A package whose architecture changed (e.g. from `amd64` to `all`) is one upgrade or
+/// downgrade rather than a removal and an installation.
+pub fn diff(before: &Status, after: &Status) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+    let moved = moved(before, after);
+
+    for (name, new) in after {
+        let old = before
+            .get(name)
+            .or_else(|| moved.get(name.as_str()).map(|old| &before[*old]));
+
+        match old {
+            None => changes.push(Change {
+                name: name.clone(),
+                kind: ChangeKind::Installed,
+                old: None,
+                new: Some(new.clone()),
+            }),
+            Some(old) if old != new || !before.contains_key(name) => changes.push(Change {
+                name: name.clone(),
+                kind: match cmp_versions(old, new) {
+                    Ordering::Greater => ChangeKind::Downgraded,
+                    _ => ChangeKind::Upgraded,
+                },
+                old: Some(old.clone()),
+                new: Some(new.clone()),
+            }),
+            _ => {}
+        }
+    }
+
+    changes.extend(
+        before
+            .iter()
+            .filter(|(name, _)| !after.contains_key(*name))
+            .filter(|(name, _)| !moved.values().any(|old| old == name))
+            .map(|(name, old)| Change {
+                name: name.clone(),
+                kind: ChangeKind::Removed,
+                old: Some(old.clone()),
+                new: None,
+            }),
+    );
+
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    changes
+}
+
+/// Packages whose architecture changed, e.g. from `amd64` to `all`: the only `name:arch`
+/// removed and the only one installed for a name, as new key to old key.
+fn moved<'a>(before: &'a Status, after: &'a Status) -> BTreeMap<&'a str, &'a str> {
+    let gone = |from: &'a Status, to: &Status| {
+        let mut keys: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for key in from.keys().filter(|key| !to.contains_key(*key)) {
+            keys.entry(package_name(key)).or_default().push(key);
+        }
+        keys
+    };
+    let removed = gone(before, after);
+
+    gone(after, before)
+        .into_iter()
+        .filter_map(|(name, installed)| {
+            match (installed.as_slice(), removed.get(name).map(Vec::as_slice)) {
+                ([new], Some([old])) => Some((*new, *old)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `name` of a `name:arch` key.
+fn package_name(key: &str) -> &str {
+    key.split_once(':').map_or(key, |(name, _)| name)
+}
+
+/// The most recent transaction recorded in `/var/log/apt/history.log`.
+#[derive(Default)]
+pub struct HistoryEntry {
+    pub start_date: Option<String>,
+    pub commandline: Option<String>,
+    pub requested_by: Option<String>,
+}
+
+pub fn last_history_entry<P: AsRef<Path>>(path: P) -> Result<HistoryEntry> {
+    Ok(parse_last_history_entry(&fs::read_to_string(path)?))
+}
+
+fn parse_last_history_entry(contents: &str) -> HistoryEntry {
+    let mut entry = HistoryEntry::default();
+
+    if let Some(stanza) = contents
+        .split("\n\n")
+        .filter(|s| !s.trim().is_empty())
+        .last()
+    {
+        for line in stanza.lines() {
+            if let Some(value) = line.strip_prefix("Start-Date:") {
+                entry.start_date = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("Commandline:") {
+                entry.commandline = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("Requested-By:") {
+                entry.requested_by = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "\
+Package: bash
+Status: install ok installed
+Architecture: amd64
+Version: 5.2.15-2+b2
+
+Package: vim
+Status: deinstall ok config-files
+Architecture: amd64
+Version: 2:9.0.1378-2
+
+Package: tzdata
+Status: install ok installed
+Architecture: all
+Version: 2024a-0+deb12u1
+";
+
+    fn status(entries: &[(&str, &str)]) -> Status {
+        entries
+            .iter()
+            .map(|(key, version)| (key.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_status_only_installed() {
+        assert_eq!(
+            parse_status(STATUS),
+            status(&[
+                ("bash:amd64", "5.2.15-2+b2"),
+                ("tzdata:all", "2024a-0+deb12u1")
+            ])
+        );
+    }
+
+    #[test]
+    fn diff_kinds() {
+        let before = status(&[
+            ("bash:amd64", "5.2.15-2"),
+            ("curl:amd64", "7.88.1-10"),
+            ("old:amd64", "1.0"),
+        ]);
+        let after = status(&[
+            ("bash:amd64", "5.2.15-2+b2"),
+            ("curl:amd64", "7.88.1-9"),
+            ("new:amd64", "1.0"),
+        ]);
+
+        let changes: Vec<(String, ChangeKind)> = diff(&before, &after)
+            .into_iter()
+            .map(|c| (c.name, c.kind))
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                (String::from("bash:amd64"), ChangeKind::Upgraded),
+                (String::from("curl:amd64"), ChangeKind::Downgraded),
+                (String::from("new:amd64"), ChangeKind::Installed),
+                (String::from("old:amd64"), ChangeKind::Removed),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_architecture_change() {
+        let before = status(&[("fonts-foo:amd64", "1.0-1")]);
+        let after = status(&[("fonts-foo:all", "1.1-1")]);
+
+        let changes = diff(&before, &after);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "fonts-foo:all");
+        assert_eq!(changes[0].kind, ChangeKind::Upgraded);
+        assert_eq!(changes[0].old.as_deref(), Some("1.0-1"));
+        assert_eq!(changes[0].new.as_deref(), Some("1.1-1"));
+    }
+
+    #[test]
+    fn diff_multiarch_removal() {
+        let before = status(&[("libc6:amd64", "2.36-9"), ("libc6:i386", "2.36-9")]);
+        let after = status(&[("libc6:amd64", "2.36-9")]);
+
+        let changes = diff(&before, &after);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].name, "libc6:i386");
+        assert_eq!(changes[0].kind, ChangeKind::Removed);
+    }
+
+    #[test]
+    fn last_history_entry_stanza() {
+        let entry = parse_last_history_entry(
+            "\
+Start-Date: 2024-05-01  10:00:00
+Commandline: apt-get install curl
+End-Date: 2024-05-01  10:00:05
+
+Start-Date: 2024-05-02  06:25:01
+Commandline: apt upgrade
+Requested-By: alice (1000)
+Upgrade: bash:amd64 (5.2.15-2, 5.2.15-2+b2)
+End-Date: 2024-05-02  06:25:09
+
+",
+        );
+
+        assert_eq!(entry.start_date.as_deref(), Some("2024-05-02  06:25:01"));
+        assert_eq!(entry.commandline.as_deref(), Some("apt upgrade"));
+        assert_eq!(entry.requested_by.as_deref(), Some("alice (1000)"));
+    }
+}
@@ -6,51 +6,163 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     Config(config::ConfigError),
     ConfigWrite(String),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Filter(String),
+    Http(String),
+    Target(String),
+    Collector(String),
+    Daemon(String),
+    Schedule(String),
+    #[cfg(feature = "collector")]
+    Database(rusqlite::Error),
     AptCache(cxx::Exception),
+    #[cfg(feature = "mail")]
     Email(lettre::error::Error),
+    #[cfg(feature = "mail")]
     SMTP(lettre::transport::smtp::Error),
+    /// A notification couldn't be sent. It's kept in the outbox, unless that failed with the
+    /// second error.
+    #[cfg(feature = "mail")]
+    Unsent(Box<Error>, Option<Box<Error>>),
     Foreign(Box<dyn error::Error + Send + Sync>),
 }
 
+impl Error {
+    /// The name of the variant, e.g. `AptCache`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Config(_) => "Config",
+            Self::ConfigWrite(_) => "ConfigWrite",
+            Self::Io(_) => "Io",
+            Self::Json(_) => "Json",
+            Self::Filter(_) => "Filter",
+            Self::Http(_) => "Http",
+            Self::Target(_) => "Target",
+            Self::Collector(_) => "Collector",
+            Self::Daemon(_) => "Daemon",
+            Self::Schedule(_) => "Schedule",
+            #[cfg(feature = "collector")]
+            Self::Database(_) => "Database",
+            Self::AptCache(_) => "AptCache",
+            #[cfg(feature = "mail")]
+            Self::Email(_) => "Email",
+            #[cfg(feature = "mail")]
+            Self::SMTP(_) => "SMTP",
+            #[cfg(feature = "mail")]
+            Self::Unsent(ref err, _) => err.kind(),
+            Self::Foreign(_) => "Foreign",
+        }
+    }
+
+    /// Whether a notification couldn't be sent, but is kept in the outbox: it's retried from
+    /// there, so it shouldn't be built and sent again.
+    pub fn is_kept_in_outbox(&self) -> bool {
+        #[cfg(feature = "mail")]
+        if let Self::Unsent(_, None) = self {
+            return true;
+        }
+
+        false
+    }
+
+    /// The error and warning lines from apt, e.g. `E:Could not open lock file`.
+    pub fn apt_lines(&self) -> Vec<String> {
+        match self {
+            Self::AptCache(err) => err
+                .what()
+                .split(';')
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Config(ref err) => write!(f, "Config Error: {}", err),
             Self::ConfigWrite(ref err) => write!(f, "Config Error: {}", err),
+            Self::Io(ref err) => write!(f, "IO Error: {}", err),
+            Self::Json(ref err) => write!(f, "JSON Error: {}", err),
+            Self::Filter(ref err) => write!(f, "Filter Error: {}", err),
+            Self::Http(ref err) => write!(f, "HTTP Error: {}", err),
+            Self::Target(ref err) => write!(f, "Target Error: {}", err),
+            Self::Collector(ref err) => write!(f, "Collector Error: {}", err),
+            Self::Daemon(ref err) => write!(f, "Daemon Error: {}", err),
+            Self::Schedule(ref err) => write!(f, "Schedule Error: {}", err),
+            #[cfg(feature = "collector")]
+            Self::Database(ref err) => write!(f, "Database Error: {}", err),
             Self::AptCache(ref err) => {
                 write!(
                     f,
-                    "APT Cache Error(s): {:?}",
-                    err.what().split(';').map(|msg| {
-                        if msg.starts_with("E:") {
-                            format!("Error: {}", &msg[2..]);
-                        } else if msg.starts_with("W:") {
-                            format!("Warning: {}", &msg[2..]);
-                        } else {
-                            format!("{}", err);
-                        }
-                    })
+                    "APT Cache Error(s): {}",
+                    err.what()
+                        .split(';')
+                        .map(|msg| {
+                            if let Some(msg) = msg.strip_prefix("E:") {
+                                format!("Error: {}", msg)
+                            } else if let Some(msg) = msg.strip_prefix("W:") {
+                                format!("Warning: {}", msg)
+                            } else {
+                                msg.to_string()
+                            }
+                        })
+                        .collect::<Vec<String>>()
+                        .join("; ")
                 )
             }
+            #[cfg(feature = "mail")]
             Self::Email(ref err) => write!(f, "Email Error: {}", err),
+            #[cfg(feature = "mail")]
             Self::SMTP(ref err) => write!(f, "SMTP Error: {}", err),
+            #[cfg(feature = "mail")]
+            Self::Unsent(ref err, None) => write!(f, "{}, kept in the outbox to retry it", err),
+            #[cfg(feature = "mail")]
+            Self::Unsent(ref err, Some(ref outbox_err)) => {
+                write!(f, "{}, and couldn't be kept in the outbox: {}", err, outbox_err)
+            }
             Self::Foreign(ref err) => write!(f, "Unknown error: {}", err),
         }
     }
 }
 
+#[cfg(feature = "mail")]
 impl From<lettre::error::Error> for Error {
     fn from(err: lettre::error::Error) -> Self {
         Self::Email(err)
     }
 }
 
+#[cfg(feature = "mail")]
 impl From<lettre::transport::smtp::Error> for Error {
     fn from(err: lettre::transport::smtp::Error) -> Self {
         Self::SMTP(err)
     }
 }
 
+#[cfg(feature = "collector")]
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
 impl From<config::ConfigError> for Error {
     fn from(err: config::ConfigError) -> Self {
         Self::Config(err)
@@ -0,0 +1,241 @@
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    mail, util,
+};
+use chrono::{DateTime, Duration, Utc};
+use lettre::message::{header, Mailbox, Mailboxes};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+const SENT_FILE: &str = "failure.json";
+
+/// How failures are notified about.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    /// Email, through the `[smtp]` relay
+    #[default]
+    Mail,
+    /// The failure as JSON, POSTed to `url`
+    Webhook,
+}
+
+/// Notifies about failures of the check itself, e.g. an unreadable config file or an apt
+/// cache that can't be opened.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct OnError {
+    pub channel: Channel,
+    /// Recipients of the email (default: those in `[mail]`)
+    to: Vec<Mailbox>,
+    /// Webhook the failure is POSTed to
+    pub url: Option<String>,
+    /// The same kind of error is notified about at most once in this many hours
+    pub interval_hours: i64,
+}
+
+impl Default for OnError {
+    fn default() -> OnError {
+        OnError {
+            channel: Channel::Mail,
+            to: Vec::new(),
+            url: None,
+            interval_hours: 24,
+        }
+    }
+}
+
+/// A failure of the check, as notified about.
+#[derive(Serialize)]
+pub struct Failure {
+    pub host: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// The [`Error`] variant, e.g. `AptCache`
+    pub kind: &'static str,
+    pub error: String,
+    /// Error and warning lines from apt
+    pub apt: Vec<String>,
+    /// Failures since the last notification that weren't notified about
+    pub suppressed: usize,
+}
+
+/// The last failure of a kind notified about.
+#[derive(Deserialize, Serialize)]
+struct Sent {
+    timestamp: DateTime<Utc>,
+    suppressed: usize,
+}
+
+/// The last notification of each kind of error.
+type SentByKind = BTreeMap<String, Sent>;
+
+impl OnError {
+    /// The recipients, if other than those in `[mail]`.
+    pub fn to(&self) -> Option<header::To> {
+        if self.to.is_empty() {
+            return None;
+        }
+
+        Some(Mailboxes::from(self.to.clone()).into())
+    }
+
+    /// Notifies about `err`, unless the same kind of error was notified about less than
+    /// `interval_hours` ago. Returns whether a notification was sent.
+    ///
+    /// SMTP errors aren't notified about by email, as the notification would go through the
+    /// same relay.
+    pub fn notify(&self, config: &Config, err: &Error) -> Result<bool> {
+        let dir = config.state.dir();
+        let now = Utc::now();
+        let kind = err.kind();
+
+        if self.channel == Channel::Mail && kind == "SMTP" {
+            return Ok(false);
+        }
+
+        let mut sent = load(&dir)?;
+        let Some(suppressed) = self.due(&mut sent, kind, now) else {
+            save(&dir, &sent)?;
+            return Ok(false);
+        };
+
+        let failure = Failure {
+            host: util::host_label(),
+            timestamp: now,
+            kind,
+            error: err.to_string(),
+            apt: err.apt_lines(),
+            suppressed,
+        };
+
+        match self.channel {
+            Channel::Mail => {
+                mail::send(config, mail::build_failure_message(config, self, &failure)?)?;
+            }
+            Channel::Webhook => self.post(&failure)?,
+        }
+
+        sent.insert(
+            kind.to_string(),
+            Sent {
+                timestamp: now,
+                suppressed: 0,
+            },
+        );
+        save(&dir, &sent)?;
+
+        Ok(true)
+    }
+
+    /// The failures of `kind` suppressed since the last notification, if one is due at `now`.
+    /// Otherwise this failure is counted as suppressed.
+    fn due(&self, sent: &mut SentByKind, kind: &str, now: DateTime<Utc>) -> Option<usize> {
+        match sent.get_mut(kind) {
+            Some(last) if now - last.timestamp < Duration::hours(self.interval_hours) => {
+                last.suppressed += 1;
+                None
+            }
+            Some(last) => Some(last.suppressed),
+            None => Some(0),
+        }
+    }
+
+    #[cfg(feature = "http")]
+    fn post(&self, failure: &Failure) -> Result<()> {
+        let url = self
+            .url
+            .as_deref()
+            .ok_or_else(|| Error::Http(String::from("no `url` for the webhook channel")))?;
+
+        ureq::post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::to_string(failure)?)
+            .map_err(|err| Error::Http(err.to_string()))?;
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn post(&self, _failure: &Failure) -> Result<()> {
+        Err(Error::Http(String::from(
+            "built without the `http` feature",
+        )))
+    }
+}
+
+fn load(dir: &Path) -> Result<SentByKind> {
+    match fs::read_to_string(dir.join(SENT_FILE)) {
+        Ok(sent) => Ok(serde_json::from_str(&sent).unwrap_or_default()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(SentByKind::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn save(dir: &Path, sent: &SentByKind) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(SENT_FILE), serde_json::to_string_pretty(sent)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn rate_limited_per_kind() {
+        let on_error = OnError::default();
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 6, 0, 0).unwrap();
+        let mut sent = SentByKind::new();
+
+        assert_eq!(on_error.due(&mut sent, "AptCache", start), Some(0));
+        sent.insert(
+            String::from("AptCache"),
+            Sent {
+                timestamp: start,
+                suppressed: 0,
+            },
+        );
+
+        let later = start + Duration::hours(1);
+        assert_eq!(on_error.due(&mut sent, "AptCache", later), None);
+        assert_eq!(on_error.due(&mut sent, "AptCache", later), None);
+        // Another kind isn't held back by the AptCache one
+        assert_eq!(on_error.due(&mut sent, "Config", later), Some(0));
+
+        let next_day = start + Duration::hours(24);
+        assert_eq!(on_error.due(&mut sent, "AptCache", next_day), Some(2));
+    }
+
+    #[test]
+    fn unreadable_state_is_reset() {
+        let dir = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-failure-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        // The format before errors were rate limited per kind
+        fs::write(
+            dir.join(SENT_FILE),
+            r#"{ "kind": "Io", "timestamp": "2024-05-01T06:00:00Z", "suppressed": 1 }"#,
+        )
+        .unwrap();
+
+        assert!(load(&dir).unwrap().is_empty());
+
+        let mut sent = SentByKind::new();
+        sent.insert(
+            String::from("Io"),
+            Sent {
+                timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 6, 0, 0).unwrap(),
+                suppressed: 3,
+            },
+        );
+        save(&dir, &sent).unwrap();
+        assert_eq!(load(&dir).unwrap()["Io"].suppressed, 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{
+    apt::Upgradeable,
+    error::{Error, Result},
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Rules deciding which upgradeable packages are reported.
+///
+/// If there are any `include` rules, only packages matching one of them are kept.
+/// Packages matching any `exclude` rule are then removed.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Filter {
+    pub include: Vec<Rule>,
+    pub exclude: Vec<Rule>,
+    pub held: Held,
+    /// Leave out updates unattended-upgrades will install
+    pub hide_auto_upgrade: bool,
+    /// Leave out phased updates apt is holding back on this machine
+    pub hide_phasing: bool,
+}
+
+/// What to do with packages held with `apt-mark hold` or pinned below their candidate.
+#[derive(Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Held {
+    /// List them with the other packages
+    #[default]
+    Show,
+    /// Leave them out
+    Hide,
+    /// List them in a separate section
+    Separate,
+}
+
+/// Packages remaining after applying a [`Filter`].
+pub struct Filtered {
+    pub packages: Vec<Upgradeable>,
+    /// Held packages, with [`Held::Separate`]
+    pub held: Vec<Upgradeable>,
+    /// Number of packages removed
+    pub suppressed: usize,
+}
+
+/// Matches packages on which all of the given patterns match.
+///
+/// Patterns are globs (e.g. `linux-image-*`), or regular expressions if `regex` is set.
+/// Both have to match the whole value. Fields with more than one value (e.g. `archive`)
+/// match if any of the values match.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Rule {
+    pub name: Option<String>,
+    pub origin: Option<String>,
+    pub archive: Option<String>,
+    pub section: Option<String>,
+    pub priority: Option<String>,
+    pub regex: bool,
+    /// Last day the rule applies
+    pub until: Option<NaiveDate>,
+}
+
+enum Pattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    fn new(pattern: &str, regex: bool) -> Result<Self> {
+        if regex {
+            regex::Regex::new(&format!("^(?:{})$", pattern))
+                .map(Self::Regex)
+                .map_err(|err| Error::Filter(format!("{}: {}", pattern, err)))
+        } else {
+            glob::Pattern::new(pattern)
+                .map(Self::Glob)
+                .map_err(|err| Error::Filter(format!("{}: {}", pattern, err)))
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Glob(pattern) => pattern.matches(value),
+            Self::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+struct CompiledRule {
+    name: Option<Pattern>,
+    origin: Option<Pattern>,
+    archive: Option<Pattern>,
+    section: Option<Pattern>,
+    priority: Option<Pattern>,
+}
+
+impl CompiledRule {
+    fn new(rule: &Rule) -> Result<Self> {
+        if [&rule.name, &rule.origin, &rule.archive, &rule.section, &rule.priority]
+            .iter()
+            .all(|pattern| pattern.is_none())
+        {
+            return Err(Error::Filter(String::from(
+                "a rule needs at least one of name, origin, archive, section or priority",
+            )));
+        }
+
+        let compile = |pattern: &Option<String>| -> Result<Option<Pattern>> {
+            pattern
+                .as_deref()
+                .map(|p| Pattern::new(p, rule.regex))
+                .transpose()
+        };
+
+        Ok(Self {
+            name: compile(&rule.name)?,
+            origin: compile(&rule.origin)?,
+            archive: compile(&rule.archive)?,
+            section: compile(&rule.section)?,
+            priority: compile(&rule.priority)?,
+        })
+    }
+
+    fn matches(&self, pkg: &Upgradeable) -> bool {
+        let single = |pattern: &Option<Pattern>, value: &str| {
+            pattern.as_ref().is_none_or(|p| p.matches(value))
+        };
+        let any = |pattern: &Option<Pattern>, values: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|p| values.split(',').any(|v| p.matches(v)))
+        };
+
+        single(&self.name, &pkg.name)
+            && any(&self.origin, &pkg.origin)
+            && any(&self.archive, &pkg.archive)
+            && single(&self.section, pkg.section.as_deref().unwrap_or_default())
+            && single(&self.priority, pkg.priority.as_deref().unwrap_or_default())
+    }
+}
+
+impl Filter {
+    /// Checks that all the rules are valid, including those whose `until` date has passed.
+    pub fn validate(&self) -> Result<()> {
+        for rule in self.include.iter().chain(&self.exclude) {
+            CompiledRule::new(rule)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the filtered packages and sets aside the held ones.
+    ///
+    /// Rules whose `until` date is before `today` are ignored.
+    pub fn apply(&self, packages: Vec<Upgradeable>, today: NaiveDate) -> Result<Filtered> {
+        let compile = |rules: &Vec<Rule>| -> Result<Vec<CompiledRule>> {
+            rules
+                .iter()
+                .filter(|rule| rule.until.is_none_or(|until| today <= until))
+                .map(CompiledRule::new)
+                .collect()
+        };
+
+        let include = compile(&self.include)?;
+        let exclude = compile(&self.exclude)?;
+
+        let count = packages.len();
+
+        let packages: Vec<Upgradeable> = packages
+            .into_iter()
+            .filter(|pkg| include.is_empty() || include.iter().any(|rule| rule.matches(pkg)))
+            .filter(|pkg| !exclude.iter().any(|rule| rule.matches(pkg)))
+            .filter(|pkg| !(self.held == Held::Hide && pkg.is_held()))
+            .filter(|pkg| !(self.hide_auto_upgrade && pkg.auto_upgrade))
+            .filter(|pkg| !(self.hide_phasing && pkg.phasing_deferred))
+            .collect();
+
+        let suppressed = count - packages.len();
+
+        let (held, packages) = packages
+            .into_iter()
+            .partition(|pkg| self.held == Held::Separate && pkg.is_held());
+
+        Ok(Filtered {
+            packages,
+            held,
+            suppressed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgradeable(name: &str, archive: &str, section: &str) -> Upgradeable {
+        Upgradeable {
+            name: name.to_string(),
+            archive: archive.to_string(),
+            origin: String::from("Debian"),
+            section: Some(section.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn packages() -> Vec<Upgradeable> {
+        vec![
+            upgradeable("linux-image-amd64", "bookworm-security", "kernel"),
+            upgradeable("libssl3", "bookworm,bookworm-security", "libs"),
+            upgradeable("vim", "bookworm", "editors"),
+        ]
+    }
+
+    fn names(filtered: &Filtered) -> Vec<&str> {
+        filtered.packages.iter().map(|pkg| pkg.name.as_str()).collect()
+    }
+
+    fn rule(name: Option<&str>, archive: Option<&str>, regex: bool) -> Rule {
+        Rule {
+            name: name.map(String::from),
+            archive: archive.map(String::from),
+            regex,
+            ..Default::default()
+        }
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+    }
+
+    #[test]
+    fn include_then_exclude() {
+        let filter = Filter {
+            include: vec![rule(None, Some("*-security"), false)],
+            exclude: vec![rule(Some("linux-image-*"), None, false)],
+            ..Default::default()
+        };
+
+        let filtered = filter.apply(packages(), today()).unwrap();
+
+        assert_eq!(names(&filtered), vec!["libssl3"]);
+        assert_eq!(filtered.suppressed, 2);
+    }
+
+    #[test]
+    fn regex_is_anchored() {
+        let filter = Filter {
+            exclude: vec![rule(Some("lib"), None, true), rule(Some("vi."), None, true)],
+            ..Default::default()
+        };
+
+        let filtered = filter.apply(packages(), today()).unwrap();
+
+        assert_eq!(names(&filtered), vec!["linux-image-amd64", "libssl3"]);
+    }
+
+    #[test]
+    fn expired_rules_are_ignored() {
+        let filter = Filter {
+            exclude: vec![Rule {
+                until: NaiveDate::from_ymd_opt(2024, 4, 30),
+                ..rule(Some("vim"), None, false)
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(filter.apply(packages(), today()).unwrap().packages.len(), 3);
+    }
+
+    #[test]
+    fn held_and_pinned_are_separated() {
+        let filter = Filter {
+            held: Held::Separate,
+            ..Default::default()
+        };
+        let mut packages = packages();
+        packages[0].held = true;
+        packages[1].pinned = true;
+
+        let filtered = filter.apply(packages, today()).unwrap();
+
+        assert_eq!(names(&filtered), vec!["vim"]);
+        assert_eq!(filtered.held.len(), 2);
+        assert_eq!(filtered.suppressed, 0);
+    }
+
+    #[test]
+    fn empty_rule_is_rejected() {
+        let filter = Filter {
+            exclude: vec![Rule::default()],
+            ..Default::default()
+        };
+
+        assert!(filter.validate().is_err());
+        assert!(filter.apply(packages(), today()).is_err());
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let filter = Filter {
+            include: vec![rule(Some("("), None, true)],
+            ..Default::default()
+        };
+
+        assert!(filter.validate().is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Just enough OpenPGP (RFC 4880) to tell which keys are in a keyring and which key signed a
+//! Release file.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1_smol::Sha1;
+use std::{collections::HashSet, fs, path::Path};
+
+/// The last 8 bytes of a v4 key's fingerprint.
+pub type KeyId = [u8; 8];
+
+const SIGNATURE: u8 = 2;
+const PUBLIC_KEY: u8 = 6;
+const PUBLIC_SUBKEY: u8 = 14;
+
+/// The IDs of the keys and subkeys in a binary (`.gpg`) or ASCII-armored (`.asc`) keyring.
+///
+/// Keys other than v4 are skipped; unreadable files have no keys.
+pub fn key_ids(file: &Path) -> HashSet<KeyId> {
+    let Ok(data) = fs::read(file) else {
+        return HashSet::new();
+    };
+    let data = dearmor(&data, "PUBLIC KEY BLOCK").unwrap_or(data);
+
+    packets(&data)
+        .into_iter()
+        .filter(|(tag, _)| *tag == PUBLIC_KEY || *tag == PUBLIC_SUBKEY)
+        .filter_map(|(_, body)| key_id(body))
+        .collect()
+}
+
+/// The IDs of the keys that made the signatures of an `InRelease` or `Release.gpg` file.
+pub fn issuers(file: &Path) -> HashSet<KeyId> {
+    let Ok(data) = fs::read(file) else {
+        return HashSet::new();
+    };
+    let data = dearmor(&data, "SIGNATURE").unwrap_or(data);
+
+    packets(&data)
+        .into_iter()
+        .filter(|(tag, _)| *tag == SIGNATURE)
+        .flat_map(|(_, body)| issuer(body))
+        .collect()
+}
+
+/// The binary data of the first armored block of the given type, e.g. `SIGNATURE` for the
+/// signature of a clearsigned message.
+fn dearmor(data: &[u8], block: &str) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(data).ok()?;
+    let begin = format!("-----BEGIN PGP {}-----", block);
+    let (_, rest) = text.split_once(&begin)?;
+    let (armored, _) = rest.split_once("-----END PGP ")?;
+
+    // Armor headers, e.g. `Version:`, end with an empty line; `=` starts the checksum
+    let base64: String = armored
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .skip_while(|line| !line.is_empty())
+        .take_while(|line| !line.starts_with('='))
+        .collect();
+
+    STANDARD.decode(base64).ok()
+}
+
+/// The tags and bodies of the packets, up to the first one that can't be parsed.
+fn packets(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut packets = Vec::new();
+
+    while let Some(&ctb) = data.first() {
+        if ctb & 0x80 == 0 {
+            break;
+        }
+
+        let (tag, header, len) = if ctb & 0x40 != 0 {
+            let Some((header, len)) = new_length(&data[1..]) else {
+                break;
+            };
+            (ctb & 0x3f, 1 + header, len)
+        } else {
+            let size = match ctb & 0x03 {
+                0 => 1,
+                1 => 2,
+                2 => 4,
+                // Indeterminate length, up to the end of the data
+                _ => {
+                    packets.push(((ctb >> 2) & 0x0f, &data[1..]));
+                    break;
+                }
+            };
+            let Some(bytes) = data.get(1..1 + size) else {
+                break;
+            };
+            let len = bytes.iter().fold(0, |len, &b| len << 8 | b as usize);
+            ((ctb >> 2) & 0x0f, 1 + size, len)
+        };
+
+        let Some(body) = data.get(header..header + len) else {
+            break;
+        };
+        packets.push((tag, body));
+        data = &data[header + len..];
+    }
+
+    packets
+}
+
+/// The size of a new format length (of a packet or subpacket) and the length it encodes.
+///
+/// Partial body lengths aren't used for keys and signatures and aren't supported.
+fn new_length(data: &[u8]) -> Option<(usize, usize)> {
+    match *data.first()? {
+        len @ 0..=191 => Some((1, len as usize)),
+        first @ 192..=223 => {
+            let second = *data.get(1)? as usize;
+            Some((2, ((first as usize - 192) << 8) + second + 192))
+        }
+        255 => {
+            let bytes = data.get(1..5)?;
+            Some((5, bytes.iter().fold(0, |len, &b| len << 8 | b as usize)))
+        }
+        _ => None,
+    }
+}
+
+/// The key ID of a v4 public key or subkey packet: the last 8 bytes of the SHA-1 of the
+/// packet, with an old format header.
+fn key_id(body: &[u8]) -> Option<KeyId> {
+    if body.first() != Some(&4) {
+        return None;
+    }
+
+    let mut sha1 = Sha1::new();
+    sha1.update(&[0x99]);
+    sha1.update(&u16::try_from(body.len()).ok()?.to_be_bytes());
+    sha1.update(body);
+
+    sha1.digest().bytes()[12..].try_into().ok()
+}
+
+/// The issuer of a v4 signature packet, from its `Issuer` or `Issuer Fingerprint` subpackets.
+fn issuer(body: &[u8]) -> Vec<KeyId> {
+    let mut issuers = Vec::new();
+
+    if body.first() != Some(&4) {
+        return issuers;
+    }
+
+    // Version, signature type, public key and hash algorithms, then the hashed and unhashed
+    // subpackets, each prefixed with their two-byte length
+    let mut rest = body.get(4..).unwrap_or_default();
+    for _ in 0..2 {
+        let Some(len) = rest
+            .get(..2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        else {
+            break;
+        };
+        let Some(mut subpackets) = rest.get(2..2 + len) else {
+            break;
+        };
+        rest = &rest[2 + len..];
+
+        while let Some((header, len)) = new_length(subpackets) {
+            let Some(subpacket) = subpackets.get(header..header + len) else {
+                break;
+            };
+            subpackets = &subpackets[header + len..];
+
+            let id: Option<KeyId> = match subpacket.split_first() {
+                // Issuer
+                Some((16, id)) => id.try_into().ok(),
+                // Issuer Fingerprint of a v4 key
+                Some((33, [4, fingerprint @ ..])) if fingerprint.len() == 20 => {
+                    fingerprint[12..].try_into().ok()
+                }
+                _ => None,
+            };
+            issuers.extend(id);
+        }
+    }
+
+    issuers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Primary key `76E5E2D98433BE17` with signing subkey `AB4CC20B54629A2A`.
+    const ARCHIVE_KEY: &str = "\
+-----BEGIN PGP PUBLIC KEY BLOCK-----
+Comment: Fixture Archive <archive@example.com>
+
+mDMEatWZwBYJKwYBBAHaRw8BAQdA+ncV4OpBUuhf5zYgHHtt0Dd3i8iuV7V6t8j+
+mcXTDDK0JUZpeHR1cmUgQXJjaGl2ZSA8YXJjaGl2ZUBleGFtcGxlLmNvbT6IkAQT
+FggAOBYhBMk7XItqmd4yPk/MDnbl4tmEM74XBQJq1ZnAAhsDBQsJCAcCBhUKCQgL
+AgQWAgMBAh4BAheAAAoJEHbl4tmEM74XlNUA/0HvTZXE98DWPwMEkRK0WTC+99LH
+HfYSLMPbQe7AsSmoAQCbRU89/IOOcDWJm+JH7gzGek4/NtraKLrv3QLoW3U/Cbgz
+BGrVmcAWCSsGAQQB2kcPAQEHQCamIUdXJ0djGMLZkW+fHVMmGTFzbTD2d95Zzir0
+Roj5iO8EGBYIACAWIQTJO1yLapneMj5PzA525eLZhDO+FwUCatWZwAIbAgCBCRB2
+5eLZhDO+F3YgBBkWCAAdFiEEn26Wh+2nM0LcQlqPq0zCC1RimioFAmrVmcAACgkQ
+q0zCC1RimioVegEA2OcR3jwTXY4EGeazXmsqdgFLChNjJz30zxiCGrA9PesA/2Kt
+eA9h6QxgyBrfsiG4fleYjgX/4PI+4wxX0I85gAkJbc8BALHpJRp97lKsaBex6APg
+Fydu198QgZwVqv8/C/dMVlXGAP49/R1NJR11qa+WS3Ce46Y5eDNMb9EsZ0ACiC9g
++z/+CQ==
+=kfJ+
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    /// Signed with the subkey of [`ARCHIVE_KEY`].
+    const IN_RELEASE: &str = "\
+-----BEGIN PGP SIGNED MESSAGE-----
+Hash: SHA256
+
+Origin: Fixture
+Suite: stable
+-----BEGIN PGP SIGNATURE-----
+
+iIoEARYIADIWIQSfbpaH7aczQtxCWo+rTMILVGKaKgUCatWZwBQcYXJjaGl2ZUBl
+eGFtcGxlLmNvbQAKCRCrTMILVGKaKhHTAQDX16Rxh7RvuHZChL2OhPbpB20DThHn
+rYXX8NKvIMMgHwD/eS8jPkKJurt46mGGeDJWRXax+MpPMKBNHJBwxQTY3w0=
+=BDOT
+-----END PGP SIGNATURE-----
+";
+
+    fn id(hex: &str) -> KeyId {
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn keys_and_issuers() {
+        let dir = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-keyring-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let armored = dir.join("archive.asc");
+        fs::write(&armored, ARCHIVE_KEY).unwrap();
+        let binary = dir.join("archive.gpg");
+        fs::write(
+            &binary,
+            dearmor(ARCHIVE_KEY.as_bytes(), "PUBLIC KEY BLOCK").unwrap(),
+        )
+        .unwrap();
+        let in_release = dir.join("InRelease");
+        fs::write(&in_release, IN_RELEASE).unwrap();
+        let release_gpg = dir.join("Release.gpg");
+        fs::write(
+            &release_gpg,
+            dearmor(IN_RELEASE.as_bytes(), "SIGNATURE").unwrap(),
+        )
+        .unwrap();
+
+        let keys = HashSet::from([id("76E5E2D98433BE17"), id("AB4CC20B54629A2A")]);
+        assert_eq!(key_ids(&armored), keys);
+        assert_eq!(key_ids(&binary), keys);
+
+        let signer = HashSet::from([id("AB4CC20B54629A2A")]);
+        assert_eq!(issuers(&in_release), signer);
+        assert_eq!(issuers(&release_gpg), signer);
+
+        assert!(key_ids(&dir.join("missing.gpg")).is_empty());
+        assert!(issuers(&armored).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
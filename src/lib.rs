@@ -0,0 +1,49 @@
+//! Checks for package updates using `libapt-pkg` and builds a report of them.
+//!
+//! ```no_run
+//! use apt_updates_notifier::{apt, report::Report};
+//!
+//! let output = apt::update()?;
+//! let packages = apt::list_upgradeable()?;
+//!
+//! let report = Report::new(packages, output);
+//! println!("{}", report.to_json()?);
+//! # Ok::<(), apt_updates_notifier::error::Error>(())
+//! ```
+//!
+//! Sending the report by email requires the `mail` feature, downloading data the `http` feature.
+
+pub mod ack;
+pub mod agent;
+#[cfg(feature = "collector")]
+pub mod collector;
+pub mod apt;
+pub mod config;
+pub mod cve;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod dpkg;
+pub mod error;
+#[cfg(feature = "mail")]
+pub mod failure;
+pub mod filter;
+mod keyring;
+#[cfg(feature = "mail")]
+pub mod mail;
+pub mod news;
+#[cfg(feature = "mail")]
+pub mod outbox;
+pub mod phasing;
+pub mod report;
+pub mod repository;
+pub mod schedule;
+pub mod sources;
+pub mod state;
+#[cfg(feature = "serve")]
+pub mod status;
+pub mod summary;
+pub mod target;
+pub mod unattended;
+mod util;
+
+pub use error::{Error, Result};
@@ -1,93 +1,570 @@
 use lettre::{
-    message::{header::ContentType, MultiPart},
-    Message, SmtpTransport, Transport,
+    message::{
+        header::{ContentType, Header, HeaderName, HeaderValue},
+        MessageBuilder, MultiPart,
+    },
+    transport::smtp::response::Response,
+    Address, Message, SmtpTransport, Transport,
 };
 
+#[cfg(feature = "collector")]
+use crate::collector::{Collector, Digest};
 use crate::{
-    apt::{ProgressOutput, Upgradeable},
+    ack::Acknowledgement,
+    apt::Upgradeable,
     config,
-    error::Result,
+    dpkg::Change,
+    error::{Error, Result},
+    failure::{Failure, OnError},
+    report::{delta_str, Combined, Report},
+    summary::{Period, Summary},
 };
+use chrono::{DateTime, Utc};
+use rust_apt::util::{unit_str, NumSys};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::PathBuf};
 
-pub fn build_message(
+/// Priority of versions from a normal archive, see apt_preferences(5).
+const DEFAULT_PIN_PRIORITY: i32 = 500;
+
+pub fn build_message(config: &config::Config, report: &Report) -> Result<Message> {
+    let packages: Vec<&Upgradeable> = report.unacknowledged().collect();
+
+    let message = notification_builder(
+        config,
+        &packages,
+        report.cve_summary(),
+        report.repositories.len(),
+        report.timestamp,
+    )?;
+
+    with_body(
+        config,
+        message,
+        make_plain(config, report),
+        make_html(config, report),
+    )
+}
+
+/// A single notification covering all targets, see [`config::Config::targets`].
+pub fn build_combined_message(config: &config::Config, combined: &Combined) -> Result<Message> {
+    let packages: Vec<&Upgradeable> = combined.unacknowledged().collect();
+
+    let message = notification_builder(
+        config,
+        &packages,
+        combined.cve_summary(),
+        combined
+            .targets
+            .iter()
+            .map(|report| report.repositories.len())
+            .sum(),
+        combined.timestamp,
+    )?;
+
+    with_body(
+        config,
+        message,
+        make_combined_plain(config, combined),
+        make_combined_html(config, combined),
+    )
+}
+
+/// The daily summary of the fleet sent by the collector.
+#[cfg(feature = "collector")]
+pub fn build_digest_message(
+    config: &config::Config,
+    collector: &Collector,
+    digest: &Digest,
+) -> Result<Message> {
+    let message = message_builder(config, collector.digest_subject_fmt(digest.pending_hosts()))?;
+
+    with_body(
+        config,
+        message,
+        make_digest_plain(config, digest),
+        make_digest_html(config, digest),
+    )
+}
+
+/// The weekly or monthly summary of the runs in `period`.
+pub fn build_summary_message(
+    config: &config::Config,
+    summary: &Summary,
+    period: &Period,
+) -> Result<Message> {
+    let message = message_builder(config, summary.subject_fmt(period.pending.len()))?;
+
+    with_body(
+        config,
+        message,
+        make_summary_plain(config, period),
+        make_summary_html(config, period),
+    )
+}
+
+/// A notification that the check itself failed.
+pub fn build_failure_message(
     config: &config::Config,
-    upgradeable: &Vec<Upgradeable>,
-    output: &Vec<ProgressOutput>,
+    on_error: &OnError,
+    failure: &Failure,
 ) -> Result<Message> {
+    let subject = format!(
+        "apt-updates-notifier failed on {}",
+        failure.host.as_deref().unwrap_or("unknown host")
+    );
+
     let message = Message::builder()
+        .sender(config.mail.from()?)
+        .from(config.mail.from()?)
+        .mailbox(on_error.to().unwrap_or(config.mail.to_header()))
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(make_failure_plain(failure))?;
+
+    Ok(message)
+}
+
+/// Subject, escalation recipients and priority for a notification about `packages`.
+fn notification_builder(
+    config: &config::Config,
+    packages: &[&Upgradeable],
+    cve_summary: Option<String>,
+    repository_problems: usize,
+    now: DateTime<Utc>,
+) -> Result<MessageBuilder> {
+    let mut subject = config.mail.subject_fmt(packages.len());
+
+    if config.cve.as_ref().is_some_and(|feed| feed.subject) {
+        if let Some(summary) = cve_summary {
+            subject = format!("{} ({})", subject, summary);
+        }
+    }
+
+    if repository_problems > 0 {
+        subject = format!("{} ({} repository problem(s))", subject, repository_problems);
+    }
+
+    let mut message = message_builder(config, subject)?;
+
+    let escalations = config.mail.escalations(packages, now);
+
+    for mbox in escalations.iter().flat_map(|rule| rule.to()) {
+        message = message.cc(mbox);
+    }
+
+    if escalations.iter().any(|rule| rule.high_priority()) {
+        message = message
+            .header(XPriority(String::from("1 (Highest)")))
+            .header(Importance(String::from("high")));
+    }
+
+    Ok(message)
+}
+
+pub fn build_dpkg_message(
+    config: &config::Config,
+    changes: &[Change],
+    user: Option<String>,
+    commandline: Option<String>,
+) -> Result<Message> {
+    let message = message_builder(config, config.mail.dpkg_subject_fmt(changes.len()))?;
+
+    with_body(
+        config,
+        message,
+        make_dpkg_plain(&config.mail, changes, &user, &commandline),
+        make_dpkg_html(&config.mail, changes, &user, &commandline),
+    )
+}
+
+fn message_builder(config: &config::Config, subject: String) -> Result<MessageBuilder> {
+    Ok(Message::builder()
         .sender(config.mail.from()?)
         .from(config.mail.from()?)
         .mailbox(config.mail.to_header())
-        .subject(config.mail.subject_fmt(upgradeable.len()));
+        .subject(subject))
+}
 
+fn with_body(
+    config: &config::Config,
+    message: MessageBuilder,
+    plain: String,
+    html: String,
+) -> Result<Message> {
     let message = if config.mail.html() {
-        message.multipart(MultiPart::alternative_plain_html(
-            make_plain(&config.mail, upgradeable, output),
-            make_html(&config.mail, upgradeable, output),
-        ))?
+        message.multipart(MultiPart::alternative_plain_html(plain, html))?
     } else {
-        message.header(ContentType::TEXT_PLAIN).body(make_plain(
-            &config.mail,
-            upgradeable,
-            output,
-        ))?
+        message.header(ContentType::TEXT_PLAIN).body(plain)?
     };
 
     Ok(message)
 }
 
-pub fn send_smtp(
+/// `X-Priority` header, used by most mail clients to flag important messages.
+#[derive(Clone)]
+struct XPriority(String);
+
+impl Header for XPriority {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("X-Priority")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// `Importance` header (RFC 2156).
+#[derive(Clone)]
+struct Importance(String);
+
+impl Header for Importance {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("Importance")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+fn mailer(config: &config::Config) -> Result<SmtpTransport> {
+    Ok(SmtpTransport::relay(&config.smtp.relay())?
+        .credentials(config.smtp.credentials())
+        .build())
+}
+
+pub fn send_smtp(config: &config::Config, message: Message) -> Result<Response> {
+    Ok(mailer(config)?.send(&message)?)
+}
+
+/// Sends `message`, keeping it in the outbox (see [`crate::outbox`]) to be retried if
+/// that fails.
+pub fn send(config: &config::Config, message: Message) -> Result<Response> {
+    let stored = Stored::new(&message);
+
+    send_smtp(config, message).map_err(|err| keep_in_outbox(config, stored, err))
+}
+
+/// Keeps `message`, which couldn't be sent because of `err`, in the outbox, returning
+/// whether that worked along with `err`.
+fn keep_in_outbox(config: &config::Config, message: Stored, err: Error) -> Error {
+    let outbox_err = config.outbox.add(&config.state.dir(), message, &err).err();
+
+    Error::Unsent(Box::new(err), outbox_err.map(Box::new))
+}
+
+/// A message stored on disk, to be sent later.
+#[derive(Deserialize, Serialize)]
+pub struct Stored {
+    from: Option<Address>,
+    to: Vec<Address>,
+    subject: String,
+    message: String,
+}
+
+impl Stored {
+    pub fn new(message: &Message) -> Self {
+        let envelope = message.envelope();
+
+        Self {
+            from: envelope.from().cloned(),
+            to: envelope.to().to_vec(),
+            subject: message
+                .headers()
+                .get_raw("Subject")
+                .unwrap_or_default()
+                .to_string(),
+            message: String::from_utf8_lossy(&message.formatted()).into_owned(),
+        }
+    }
+
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn send(&self, config: &config::Config) -> Result<Response> {
+        let envelope = lettre::address::Envelope::new(self.from.clone(), self.to.clone())?;
+
+        Ok(mailer(config)?.send_raw(&envelope, self.message.as_bytes())?)
+    }
+}
+
+/// Outcome of [`deliver`].
+pub enum Delivery {
+    Sent(Response),
+    /// Queued until the end of the quiet hours
+    Queued(DateTime<Utc>),
+}
+
+fn queue_path(config: &config::Config) -> PathBuf {
+    config.state.dir().join("queued.json")
+}
+
+/// Sends `message`, unless it's quiet hours (see [`crate::schedule`]) and neither `security`
+/// (it lists security updates) nor `errors` (it reports apt errors) bypass them, in which
+/// case it's queued for [`flush_queued`].
+///
+/// Only one notification is queued: a newer one replaces it, and sending one discards it.
+pub fn deliver(
     config: &config::Config,
     message: Message,
-) -> Result<lettre::transport::smtp::response::Response> {
-    let mailer = SmtpTransport::relay(&config.smtp.relay())?
-        .credentials(config.smtp.credentials())
-        .build();
+    security: bool,
+    errors: bool,
+) -> Result<Delivery> {
+    if !config.schedule.bypasses(security, errors) {
+        if let Some(until) = config.schedule.quiet_until(Utc::now())? {
+            fs::create_dir_all(config.state.dir())?;
+            fs::write(
+                queue_path(config),
+                serde_json::to_string(&Stored::new(&message))?,
+            )?;
 
-    Ok(mailer.send(&message)?)
+            return Ok(Delivery::Queued(until));
+        }
+    }
+
+    let response = send(config, message)?;
+    discard_queued(config)?;
+
+    Ok(Delivery::Sent(response))
 }
 
-fn make_plain(
-    mail_config: &config::Mail,
-    upgradeable: &Vec<Upgradeable>,
-    output: &Vec<ProgressOutput>,
-) -> String {
+/// Sends the queued notification, if any and the quiet hours are over.
+pub fn flush_queued(config: &config::Config) -> Result<Option<Response>> {
+    if config.schedule.is_quiet(Utc::now())? {
+        return Ok(None);
+    }
+
+    let queued: Stored = match fs::read_to_string(queue_path(config)) {
+        Ok(queued) => serde_json::from_str(&queued)?,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    // Moved to the outbox if it can't be sent, so that it isn't lost
+    let response = queued.send(config).map_err(|err| keep_in_outbox(config, queued, err));
+    discard_queued(config)?;
+    let response = response?;
+
+    Ok(Some(response))
+}
+
+/// Drops the queued notification, e.g. when there's nothing left to notify about.
+pub fn discard_queued(config: &config::Config) -> Result<()> {
+    match fs::remove_file(queue_path(config)) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn make_plain(config: &config::Config, report: &Report) -> String {
+    let mail_config = &config.mail;
     let mut buf: Vec<String> = Vec::new();
 
     if let Some(prepend) = mail_config.prepend() {
         buf.push(prepend);
     }
 
-    buf.push(String::from("# Packages\n"));
+    buf.extend(plain_report(config, report));
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
 
-    buf.extend(upgradeable.iter().map(|pkg| {
+    buf.join("\n")
+}
+
+fn make_combined_plain(config: &config::Config, combined: &Combined) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.push(String::from("# Targets\n"));
+    buf.extend(combined.targets.iter().map(|report| {
         format!(
-            //"{}@{}\t➭\t{}",
-            "{}/{} {} {} [upgradeable from: {}]",
-            pkg.name,
-			pkg.archive,
-			pkg.arch,
-            pkg.candidate.clone().unwrap_or("".to_string()),
-            pkg.installed.clone().unwrap_or("".to_string()),
+            "{}: {}{}",
+            report.host.clone().unwrap_or_default(),
+            target_summary(report),
+            if report.reboot.required {
+                ", restart required"
+            } else {
+                ""
+            }
         )
     }));
 
+    for report in &combined.targets {
+        buf.push(format!(
+            "\n=== {} ===\n",
+            report.host.clone().unwrap_or_default()
+        ));
+        buf.extend(plain_report(config, report));
+    }
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.join("\n")
+}
+
+/// The sections describing one report, without `prepend` and `append`.
+fn plain_report(config: &config::Config, report: &Report) -> Vec<String> {
+    let mut buf: Vec<String> = Vec::new();
+
+    if report.reboot.required {
+        buf.push(String::from("*** System restart required ***\n"));
+    }
+
+    let news = report.news();
+    if !news.is_empty() {
+        buf.push(String::from("# Action needed: read before upgrading\n"));
+        buf.extend(news.iter().map(|entry| format!("{}\n", entry.text)));
+    }
+
+    buf.push(format!("{}\n", report.summary()));
+
+    buf.push(String::from("# Packages\n"));
+
+    let packages = report
+        .packages
+        .iter()
+        .filter(|pkg| !(config.ack.hide() && pkg.acknowledged.is_some()));
+
+    match config.mail.group_by() {
+        config::GroupBy::None => buf.extend(packages.map(|pkg| plain_package(pkg, report))),
+        group_by => {
+            for (key, group) in group(packages, group_by) {
+                buf.push(format!("{} ({} package(s))", key, group.len()));
+                buf.extend(
+                    group
+                        .iter()
+                        .map(|pkg| format!("    {}", plain_package(pkg, report))),
+                );
+            }
+        }
+    }
+
+    if report.suppressed > 0 {
+        buf.push(format!("\n{} package(s) suppressed by filters", report.suppressed));
+    }
+
+    let hidden = hidden_acknowledged(config, report);
+    if hidden > 0 {
+        buf.push(format!("{} acknowledged package(s) not shown", hidden));
+    }
+
+    if !report.held.is_empty() {
+        buf.push(String::from("\n# Held back deliberately\n"));
+        buf.extend(report.held.iter().map(|pkg| plain_package(pkg, report)));
+    }
+
+    if !report.repositories.is_empty() {
+        buf.push(String::from("\n# Repository problems\n"));
+        buf.extend(
+            report
+                .repositories
+                .iter()
+                .map(|issue| format!("{}: {}\n    {}", issue.source, issue.problem, issue.message)),
+        );
+    }
+
+    if !report.sources.is_empty() {
+        buf.push(String::from("\n# Sources\n"));
+        buf.extend(report.sources.iter().map(|finding| {
+            format!(
+                "{}:{}: {}\n    {}",
+                finding.file.display(),
+                finding.line,
+                finding.source,
+                finding.message
+            )
+        }));
+    }
+
     buf.push(String::from("\n"));
     buf.push(String::from("# Output\n"));
-    buf.extend(output.iter().map(|o| format!("{}", o)));
+    buf.extend(report.output());
+
+    buf
+}
+
+fn plain_package(pkg: &Upgradeable, report: &Report) -> String {
+    let mut line = format!(
+        //"{}@{}\t➭\t{}",
+        "{}/{} {} {} [upgradeable from: {}] ({}, {}) ({})",
+        pkg.name,
+        pkg.archive,
+        pkg.arch,
+        pkg.candidate.clone().unwrap_or("".to_string()),
+        pkg.installed.clone().unwrap_or("".to_string()),
+        unit_str(pkg.download_size, NumSys::Decimal),
+        delta_str(pkg.installed_size_delta),
+        pending_str(pkg.pending_days(report.timestamp)),
+    );
+
+    if let Some(policy) = policy_str(pkg) {
+        line.push_str(&format!(" [{}]", policy));
+    }
+
+    if let Some(ack) = &pkg.acknowledged {
+        line.push_str(&format!(" [acknowledged{}]", ack_str(ack)));
+    }
+
+    if !pkg.cves.is_empty() {
+        line.push_str(&format!("\n    fixes: {}", cves_str(pkg)));
+    }
+
+    line
+}
+
+/// The HTML body of the notification for `report`.
+pub fn make_html(config: &config::Config, report: &Report) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    buf.push(String::from(
+        r#"<!DOCTYPE html>
+    <html>
+    <head>
+    </head>
+    <body>"#,
+    ));
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.extend(html_report(config, report));
 
     if let Some(append) = mail_config.append() {
         buf.push(append);
     }
 
+    buf.push(String::from(
+        r#"
+    </body>
+    </html>"#,
+    ));
+
     buf.join("\n")
 }
 
-fn make_html(
-    mail_config: &config::Mail,
-    upgradeable: &Vec<Upgradeable>,
-    output: &Vec<ProgressOutput>,
-) -> String {
+fn make_combined_html(config: &config::Config, combined: &Combined) -> String {
+    let mail_config = &config.mail;
     let mut buf: Vec<String> = Vec::new();
 
     buf.push(String::from(
@@ -102,6 +579,85 @@ fn make_html(
         buf.push(prepend);
     }
 
+    buf.push(String::from(
+        r#"        <h1>Targets</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Target</th>
+                <th>Updates</th>
+                <th>Restart required</th>
+            </tr>"#,
+    ));
+    buf.extend(combined.targets.iter().map(|report| {
+        format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            html_escape(&report.host.clone().unwrap_or_default()),
+            target_summary(report),
+            if report.reboot.required { "yes" } else { "no" }
+        )
+    }));
+    buf.push(String::from(r#"        </table>"#));
+
+    for report in &combined.targets {
+        buf.push(format!(
+            r#"<hr><h1>{}</h1>"#,
+            html_escape(&report.host.clone().unwrap_or_default())
+        ));
+        buf.extend(html_report(config, report));
+    }
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.push(String::from(
+        r#"
+    </body>
+    </html>"#,
+    ));
+
+    buf.join("\n")
+}
+
+/// e.g. "3 package(s) (1 security), 2 acknowledged"
+fn target_summary(report: &Report) -> String {
+    let mut summary = format!(
+        "{} package(s) ({} security)",
+        report.packages.len(),
+        report.packages.iter().filter(|pkg| pkg.is_security()).count()
+    );
+
+    let acknowledged = report.packages.len() - report.unacknowledged().count();
+    if acknowledged > 0 {
+        summary.push_str(&format!(", {} acknowledged", acknowledged));
+    }
+
+    summary
+}
+
+/// The sections describing one report, without `prepend` and `append`.
+fn html_report(config: &config::Config, report: &Report) -> Vec<String> {
+    let mut buf: Vec<String> = Vec::new();
+
+    if report.reboot.required {
+        buf.push(String::from(r#"        <p><strong>System restart required</strong></p>"#));
+    }
+
+    let news = report.news();
+    if !news.is_empty() {
+        buf.push(String::from(
+            r#"        <h1 style="color: #cc0000">Action needed: read before upgrading</h1>"#,
+        ));
+        buf.extend(news.iter().map(|entry| {
+            format!(
+                r#"<pre style="border-left: 4px solid #cc0000; padding-left: 8px">{}</pre>"#,
+                html_escape(&entry.text)
+            )
+        }));
+    }
+
+    buf.push(format!("<p>{}</p>", report.summary()));
+
     buf.push(String::from(
         r#"        <h1>Packages</h1>
         <table border="1" rules="all">
@@ -109,23 +665,505 @@ fn make_html(
                 <th>Package</th>
                 <th>Installed</th>
                 <th>Candidate</th>
+                <th>Size</th>
+                <th>Pending</th>
             </tr>"#,
     ));
 
-    buf.extend(upgradeable.iter().map(|pkg| {
+    let packages = report
+        .packages
+        .iter()
+        .filter(|pkg| !(config.ack.hide() && pkg.acknowledged.is_some()));
+
+    match config.mail.group_by() {
+        config::GroupBy::None => buf.extend(packages.map(|pkg| html_package(pkg, report))),
+        group_by => {
+            // Clients without <details> show the rest of a large group expanded
+            for (key, group) in group(packages, group_by) {
+                let (shown, more) = limit(&group, config.mail.group_limit());
+
+                buf.push(format!(
+                    r#"<tr style="background-color: #eeeeee"><td colspan="5"><b><code>{}</code></b> ({} package(s))</td></tr>"#,
+                    html_escape(&key),
+                    group.len()
+                ));
+                buf.extend(shown.iter().map(|pkg| html_package(pkg, report)));
+                if !more.is_empty() {
+                    buf.push(format!(
+                        r#"<tr><td colspan="5"><details><summary><small>... and {} more</small></summary><table border="1" rules="all">"#,
+                        more.len()
+                    ));
+                    buf.extend(more.iter().map(|pkg| html_package(pkg, report)));
+                    buf.push(String::from("</table></details></td></tr>"));
+                }
+            }
+        }
+    }
+
+    buf.push(String::from(r#"        </table>"#));
+
+    if report.suppressed > 0 {
+        buf.push(format!(
+            "<p>{} package(s) suppressed by filters</p>",
+            report.suppressed
+        ));
+    }
+
+    let hidden = hidden_acknowledged(config, report);
+    if hidden > 0 {
+        buf.push(format!("<p>{} acknowledged package(s) not shown</p>", hidden));
+    }
+
+    if !report.held.is_empty() {
+        buf.push(String::from(
+            r#"        <h2>Held back deliberately</h2>
+        <table border="1" rules="all">
+            <tr>
+                <th>Package</th>
+                <th>Installed</th>
+                <th>Candidate</th>
+                <th>Size</th>
+                <th>Pending</th>
+            </tr>"#,
+        ));
+        buf.extend(report.held.iter().map(|pkg| html_package(pkg, report)));
+        buf.push(String::from(r#"        </table>"#));
+    }
+
+    if !report.repositories.is_empty() {
+        buf.push(String::from(
+            r#"        <h1>Repository problems</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Source</th>
+                <th>Problem</th>
+                <th>Details</th>
+            </tr>"#,
+        ));
+        buf.extend(report.repositories.iter().map(|issue| {
+            format!(
+                "<tr><td><code>{}</code></td><td>{}</td><td><small>{}</small></td></tr>",
+                html_escape(&issue.source),
+                issue.problem,
+                html_escape(&issue.message),
+            )
+        }));
+        buf.push(String::from(r#"        </table>"#));
+    }
+
+    if !report.sources.is_empty() {
+        buf.push(String::from(
+            r#"        <h1>Sources</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>File</th>
+                <th>Source</th>
+                <th>Finding</th>
+            </tr>"#,
+        ));
+        buf.extend(report.sources.iter().map(|finding| {
+            format!(
+                "<tr><td><small>{}:{}</small></td><td><code>{}</code></td><td>{}</td></tr>",
+                html_escape(&finding.file.display().to_string()),
+                finding.line,
+                html_escape(&finding.source),
+                html_escape(&finding.message),
+            )
+        }));
+        buf.push(String::from(r#"        </table>"#));
+    }
+
+    buf.push(String::from(r#"        <h1>Output</h1><pre>"#));
+    buf.extend(report.output());
+    buf.push(String::from(r#"</pre>"#));
+
+    buf
+}
+
+fn html_package(pkg: &Upgradeable, report: &Report) -> String {
+    let (style, cells) = html_package_cells(pkg, report);
+
+    format!("<tr{}>{}</tr>", style, cells)
+}
+
+/// Style of the row and its cells: package, installed, candidate, size and pending.
+fn html_package_cells(pkg: &Upgradeable, report: &Report) -> (&'static str, String) {
+    let (style, pending) = match &pkg.acknowledged {
+        Some(ack) => (
+            r#" style="color: #999999""#,
+            format!("acknowledged{}", ack_str(ack)),
+        ),
+        None => ("", pending_str(pkg.pending_days(report.timestamp))),
+    };
+
+    // Reports received by the collector come from other hosts, so everything is escaped
+    let mut policy = policy_str(pkg)
+        .map(|policy| format!("<br><small>{}</small>", html_escape(&policy)))
+        .unwrap_or_default();
+
+    if !pkg.cves.is_empty() {
+        policy.push_str(&format!(
+            "<br><small>fixes: {}</small>",
+            html_escape(&cves_str(pkg))
+        ));
+    }
+
+    let cells = format!(
+        "<td><code>{}</code><br><code>/{} {}</code>{}</td><td><code>{}</code></td><td><code>{}</code></td><td>{}<br>{}</td><td>{}</td>",
+        html_escape(&pkg.name),
+        html_escape(&pkg.archive),
+        html_escape(&pkg.arch),
+        policy,
+        html_escape(&pkg.installed.clone().unwrap_or_default()),
+        html_escape(&pkg.candidate.clone().unwrap_or_default()),
+        unit_str(pkg.download_size, NumSys::Decimal),
+        delta_str(pkg.installed_size_delta),
+        html_escape(&pending),
+    );
+
+    (style, cells)
+}
+
+/// Hold status and pin priority, if they differ from the defaults, and whether unattended-upgrades will install it.
+fn policy_str(pkg: &Upgradeable) -> Option<String> {
+    let mut policy: Vec<String> = Vec::new();
+
+    if pkg.auto_upgrade {
+        policy.push(String::from("will be auto-installed"));
+    }
+    if pkg.manual {
+        policy.push(String::from("needs manual action"));
+    }
+
+    if pkg.phasing_deferred {
+        policy.push(format!(
+            "phasing ({}%), not yet for this machine",
+            pkg.phased_percentage.unwrap_or_default()
+        ));
+    }
+
+    if pkg.held {
+        policy.push(String::from("held"));
+    }
+    if pkg.pinned {
+        policy.push(format!(
+            "pinned, {} available",
+            pkg.available.as_deref().unwrap_or_default()
+        ));
+    }
+    if let Some(priority) = pkg.pin_priority.filter(|p| *p != DEFAULT_PIN_PRIORITY) {
+        policy.push(format!("pin {}", priority));
+    }
+
+    if policy.is_empty() {
+        None
+    } else {
+        Some(policy.join(", "))
+    }
+}
+
+/// Groups the packages by source package, origin or section, sorted by the group name.
+fn group<'a>(
+    packages: impl Iterator<Item = &'a Upgradeable>,
+    group_by: config::GroupBy,
+) -> BTreeMap<String, Vec<&'a Upgradeable>> {
+    let mut groups: BTreeMap<String, Vec<&Upgradeable>> = BTreeMap::new();
+
+    for pkg in packages {
+        let key = match group_by {
+            config::GroupBy::None => String::new(),
+            config::GroupBy::Source => format!("{} {}", pkg.source_name, pkg.source_version),
+            config::GroupBy::Origin => pkg.origin.clone(),
+            config::GroupBy::Section => pkg.section.clone().unwrap_or_default(),
+        };
+        groups.entry(key).or_default().push(pkg);
+    }
+
+    groups
+}
+
+/// The first `limit` packages of a group, and the others.
+fn limit<'a, 'b>(
+    group: &'b [&'a Upgradeable],
+    limit: usize,
+) -> (&'b [&'a Upgradeable], &'b [&'a Upgradeable]) {
+    group.split_at(group.len().min(limit))
+}
+
+/// e.g. "CVE-2023-1234 (high), CVE-2023-5678 (low)"
+fn cves_str(pkg: &Upgradeable) -> String {
+    pkg.cves
+        .iter()
+        .map(|cve| format!("{} ({})", cve.id, cve.severity))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(feature = "collector")]
+fn make_digest_plain(config: &config::Config, digest: &Digest) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.push(String::from("# Hosts\n"));
+    buf.extend(digest.hosts.iter().map(|host| {
         format!(
-            "<tr><td><code>{}</code><br><code>/{} {}</code></td><td><code>{}</code></td><td><code>{}</code></td></tr>",
-            pkg.name,
-			pkg.archive,
-			pkg.arch,
-            pkg.installed.clone().unwrap_or("".to_string()),
-            pkg.candidate.clone().unwrap_or("".to_string()),
+            "{}: {} pending ({} security), last report {}{}",
+            host.label(),
+            host.pending(),
+            host.security(),
+            host.received.format("%Y-%m-%d %H:%M"),
+            if host.report.reboot.required {
+                ", restart required"
+            } else {
+                ""
+            }
         )
     }));
 
-    buf.push(String::from(r#"        </table><h1>Output</h1><pre>"#));
-    buf.extend(output.iter().map(|o| format!("{}", o)));
-    buf.push(String::from(r#"</pre>"#));
+    let widespread = digest.widespread();
+    if !widespread.is_empty() {
+        buf.push(String::from("\n# Most widespread updates\n"));
+        buf.extend(widespread.iter().map(|(count, pkg, report)| {
+            format!("{} host(s): {}", count, plain_package(pkg, report))
+        }));
+    }
+
+    if !digest.stale.is_empty() {
+        buf.push(String::from("\n# Stopped reporting\n"));
+        buf.extend(digest.stale.iter().map(|host| {
+            format!(
+                "{}: last report {}",
+                host.label(),
+                host.received.format("%Y-%m-%d %H:%M")
+            )
+        }));
+    }
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.join("\n")
+}
+
+#[cfg(feature = "collector")]
+fn make_digest_html(config: &config::Config, digest: &Digest) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    buf.push(String::from(
+        r#"<!DOCTYPE html>
+    <html>
+    <head>
+    </head>
+    <body>"#,
+    ));
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.push(String::from(
+        r#"        <h1>Hosts</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Host</th>
+                <th>Pending</th>
+                <th>Security</th>
+                <th>Restart required</th>
+                <th>Last report</th>
+            </tr>"#,
+    ));
+    buf.extend(digest.hosts.iter().map(|host| {
+        format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            html_escape(&host.label()),
+            host.pending(),
+            host.security(),
+            if host.report.reboot.required {
+                "yes"
+            } else {
+                "no"
+            },
+            host.received.format("%Y-%m-%d %H:%M"),
+        )
+    }));
+    buf.push(String::from(r#"        </table>"#));
+
+    let widespread = digest.widespread();
+    if !widespread.is_empty() {
+        buf.push(String::from(
+            r#"        <h1>Most widespread updates</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Hosts</th>
+                <th>Package</th>
+                <th>Installed</th>
+                <th>Candidate</th>
+                <th>Size</th>
+                <th>Pending</th>
+            </tr>"#,
+        ));
+        buf.extend(widespread.iter().map(|(count, pkg, report)| {
+            let (style, cells) = html_package_cells(pkg, report);
+            format!("<tr{}><td>{}</td>{}</tr>", style, count, cells)
+        }));
+        buf.push(String::from(r#"        </table>"#));
+    }
+
+    if !digest.stale.is_empty() {
+        buf.push(String::from(r#"        <h1>Stopped reporting</h1><ul>"#));
+        buf.extend(digest.stale.iter().map(|host| {
+            format!(
+                "<li>{}: last report {}</li>",
+                html_escape(&host.label()),
+                host.received.format("%Y-%m-%d %H:%M")
+            )
+        }));
+        buf.push(String::from(r#"</ul>"#));
+    }
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.push(String::from(
+        r#"
+    </body>
+    </html>"#,
+    ));
+
+    buf.join("\n")
+}
+
+fn make_summary_plain(config: &config::Config, period: &Period) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.push(format!(
+        "{} to {}: {} run(s), {} with apt errors",
+        period.start.format("%Y-%m-%d"),
+        period.end.format("%Y-%m-%d"),
+        period.runs,
+        period.error_runs
+    ));
+
+    if !period.pending.is_empty() {
+        buf.push(String::from("\n# Still pending\n"));
+        buf.extend(period.pending.iter().map(|pkg| {
+            format!(
+                "{} {} [upgradeable from: {}] ({}){}",
+                pkg.name,
+                pkg.candidate.clone().unwrap_or_default(),
+                pkg.installed.clone().unwrap_or_default(),
+                pending_str(pkg.pending_since.map(|since| (period.end - since).num_days())),
+                if pkg.security { " [security]" } else { "" }
+            )
+        }));
+    }
+
+    if !period.installed.is_empty() {
+        buf.push(String::from("\n# Installed\n"));
+        buf.extend(period.installed.iter().map(|seen| {
+            format!(
+                "{} {} (pending {} to {})",
+                seen.package.name,
+                seen.package.candidate.clone().unwrap_or_default(),
+                seen.first_seen.format("%Y-%m-%d"),
+                seen.last_seen.format("%Y-%m-%d")
+            )
+        }));
+    }
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.join("\n")
+}
+
+fn make_summary_html(config: &config::Config, period: &Period) -> String {
+    let mail_config = &config.mail;
+    let mut buf: Vec<String> = Vec::new();
+
+    buf.push(String::from(
+        r#"<!DOCTYPE html>
+    <html>
+    <head>
+    </head>
+    <body>"#,
+    ));
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    buf.push(format!(
+        "<p>{} to {}: {} run(s), {} with apt errors</p>",
+        period.start.format("%Y-%m-%d"),
+        period.end.format("%Y-%m-%d"),
+        period.runs,
+        period.error_runs
+    ));
+
+    if !period.pending.is_empty() {
+        buf.push(String::from(
+            r#"        <h1>Still pending</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Package</th>
+                <th>Installed</th>
+                <th>Candidate</th>
+                <th>Pending</th>
+            </tr>"#,
+        ));
+        buf.extend(period.pending.iter().map(|pkg| {
+            format!(
+                "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                if pkg.security {
+                    r#" style="color: red""#
+                } else {
+                    ""
+                },
+                html_escape(&pkg.name),
+                html_escape(&pkg.installed.clone().unwrap_or_default()),
+                html_escape(&pkg.candidate.clone().unwrap_or_default()),
+                pending_str(pkg.pending_since.map(|since| (period.end - since).num_days())),
+            )
+        }));
+        buf.push(String::from(r#"        </table>"#));
+    }
+
+    if !period.installed.is_empty() {
+        buf.push(String::from(
+            r#"        <h1>Installed</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Package</th>
+                <th>Version</th>
+                <th>First seen</th>
+                <th>Last seen</th>
+            </tr>"#,
+        ));
+        buf.extend(period.installed.iter().map(|seen| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(&seen.package.name),
+                html_escape(&seen.package.candidate.clone().unwrap_or_default()),
+                seen.first_seen.format("%Y-%m-%d"),
+                seen.last_seen.format("%Y-%m-%d"),
+            )
+        }));
+        buf.push(String::from(r#"        </table>"#));
+    }
 
     if let Some(append) = mail_config.append() {
         buf.push(append);
@@ -139,3 +1177,271 @@ fn make_html(
 
     buf.join("\n")
 }
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn hidden_acknowledged(config: &config::Config, report: &Report) -> usize {
+    if config.ack.hide() {
+        report.packages.len() - report.unacknowledged().count()
+    } else {
+        0
+    }
+}
+
+fn ack_str(ack: &Acknowledgement) -> String {
+    let mut buf = String::new();
+
+    if let Some(until) = ack.until {
+        buf.push_str(&format!(" until {}", until));
+    }
+    if let Some(reason) = &ack.reason {
+        buf.push_str(&format!(": {}", reason));
+    }
+
+    buf
+}
+
+fn pending_str(days: Option<i64>) -> String {
+    match days {
+        None | Some(0) => String::from("new"),
+        Some(1) => String::from("pending for 1 day"),
+        Some(days) => format!("pending for {} days", days),
+    }
+}
+
+fn make_failure_plain(failure: &Failure) -> String {
+    let mut buf: Vec<String> = vec![
+        format!("{} ({})", failure.error, failure.kind),
+        format!("at {}", failure.timestamp.format("%Y-%m-%d %H:%M")),
+    ];
+
+    if !failure.apt.is_empty() {
+        buf.push(String::from("\n# apt\n"));
+        buf.extend(failure.apt.iter().cloned());
+    }
+
+    if failure.suppressed > 0 {
+        buf.push(format!(
+            "\n{} more failure(s) since the previous notification",
+            failure.suppressed
+        ));
+    }
+
+    buf.join("\n")
+}
+
+fn make_dpkg_plain(
+    mail_config: &config::Mail,
+    changes: &[Change],
+    user: &Option<String>,
+    commandline: &Option<String>,
+) -> String {
+    let mut buf: Vec<String> = Vec::new();
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    if let Some(user) = user {
+        buf.push(format!("User: {}", user));
+    }
+    if let Some(commandline) = commandline {
+        buf.push(format!("Command: {}", commandline));
+    }
+
+    buf.push(String::from("\n# Changes\n"));
+
+    buf.extend(changes.iter().map(|change| {
+        format!(
+            "{} {} [{} -> {}]",
+            change.kind,
+            change.name,
+            change.old.clone().unwrap_or("".to_string()),
+            change.new.clone().unwrap_or("".to_string()),
+        )
+    }));
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.join("\n")
+}
+
+fn make_dpkg_html(
+    mail_config: &config::Mail,
+    changes: &[Change],
+    user: &Option<String>,
+    commandline: &Option<String>,
+) -> String {
+    let mut buf: Vec<String> = Vec::new();
+
+    buf.push(String::from(
+        r#"<!DOCTYPE html>
+    <html>
+    <head>
+    </head>
+    <body>"#,
+    ));
+
+    if let Some(prepend) = mail_config.prepend() {
+        buf.push(prepend);
+    }
+
+    if let Some(user) = user {
+        buf.push(format!("<p>User: <code>{}</code></p>", html_escape(user)));
+    }
+    if let Some(commandline) = commandline {
+        buf.push(format!(
+            "<p>Command: <code>{}</code></p>",
+            html_escape(commandline)
+        ));
+    }
+
+    buf.push(String::from(
+        r#"        <h1>Changes</h1>
+        <table border="1" rules="all">
+            <tr>
+                <th>Action</th>
+                <th>Package</th>
+                <th>Old</th>
+                <th>New</th>
+            </tr>"#,
+    ));
+
+    buf.extend(changes.iter().map(|change| {
+        format!(
+            "<tr><td>{}</td><td><code>{}</code></td><td><code>{}</code></td><td><code>{}</code></td></tr>",
+            change.kind,
+            html_escape(&change.name),
+            html_escape(&change.old.clone().unwrap_or_default()),
+            html_escape(&change.new.clone().unwrap_or_default()),
+        )
+    }));
+
+    buf.push(String::from(r#"        </table>"#));
+
+    if let Some(append) = mail_config.append() {
+        buf.push(append);
+    }
+
+    buf.push(String::from(
+        r#"
+    </body>
+    </html>"#,
+    ));
+
+    buf.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgradeable(name: &str, source: &str) -> Upgradeable {
+        Upgradeable {
+            name: name.to_string(),
+            source_name: source.to_string(),
+            source_version: String::from("2.36-9+deb12u4"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn group_by_source() {
+        let packages = [
+            upgradeable("libc6", "glibc"),
+            upgradeable("bash", "bash"),
+            upgradeable("libc-bin", "glibc"),
+        ];
+
+        let groups = group(packages.iter(), config::GroupBy::Source);
+        let names: Vec<(&str, Vec<&str>)> = groups
+            .iter()
+            .map(|(key, group)| {
+                (
+                    key.as_str(),
+                    group.iter().map(|pkg| pkg.name.as_str()).collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                ("bash 2.36-9+deb12u4", vec!["bash"]),
+                ("glibc 2.36-9+deb12u4", vec!["libc6", "libc-bin"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_group() {
+        let packages: Vec<Upgradeable> = (0..7)
+            .map(|i| upgradeable(&format!("pkg{}", i), "src"))
+            .collect();
+        let group: Vec<&Upgradeable> = packages.iter().collect();
+
+        let (shown, more) = limit(&group, 5);
+        assert_eq!(shown.len(), 5);
+        assert_eq!(more.len(), 2);
+        assert_eq!(more[0].name, "pkg5");
+
+        let (shown, more) = limit(&group[..3], 5);
+        assert_eq!(shown.len(), 3);
+        assert!(more.is_empty());
+    }
+
+    #[test]
+    fn grouped_packages_are_all_listed() {
+        let config = config::Config {
+            mail: serde_json::from_str(r#"{ "group_by": "source", "group_limit": 2 }"#).unwrap(),
+            ..Default::default()
+        };
+        let report = Report {
+            packages: ["libc6", "libc-bin", "libc-l10n", "locales"]
+                .map(|name| upgradeable(name, "glibc"))
+                .into(),
+            ..Default::default()
+        };
+
+        let plain = plain_report(&config, &report).join("\n");
+        assert!(plain.contains("glibc 2.36-9+deb12u4 (4 package(s))"));
+        for name in ["libc6", "libc-bin", "libc-l10n", "locales"] {
+            assert!(plain.contains(&format!("    {}", name)));
+        }
+
+        let html = html_report(&config, &report).join("\n");
+        let (shown, more) = html.split_once("<details>").unwrap();
+        assert!(shown.contains("libc6") && shown.contains("libc-bin"));
+        assert!(more.contains("... and 2 more"));
+        assert!(more.contains("libc-l10n") && more.contains("locales"));
+    }
+
+    #[test]
+    fn dpkg_html_is_escaped() {
+        let changes = [Change {
+            name: String::from("foo<bar"),
+            kind: crate::dpkg::ChangeKind::Installed,
+            old: None,
+            new: Some(String::from("1.0&1")),
+        }];
+
+        let html = make_dpkg_html(
+            &config::Mail::default(),
+            &changes,
+            &Some(String::from("<admin>")),
+            &Some(String::from("apt install 'foo<bar' && true")),
+        );
+
+        assert!(html.contains("<p>User: <code>&lt;admin&gt;</code></p>"));
+        assert!(html.contains("<code>apt install 'foo&lt;bar' &amp;&amp; true</code>"));
+        assert!(html.contains("<code>foo&lt;bar</code>"));
+        assert!(html.contains("<code>1.0&amp;1</code>"));
+        assert!(!html.contains("foo<bar"));
+    }
+}
@@ -1,39 +1,501 @@
-mod apt;
 mod cli;
-mod config;
-mod error;
-mod mail;
-mod util;
 
-use error::Result;
+use apt_updates_notifier::{
+    ack, apt, config, cve, dpkg, mail, report, state, summary, Error, Result,
+};
+#[cfg(feature = "collector")]
+use apt_updates_notifier::collector;
+#[cfg(feature = "daemon")]
+use apt_updates_notifier::daemon::Trigger;
+use chrono::{Local, Utc};
+#[cfg(feature = "daemon")]
+use std::collections::BTreeSet;
+use std::path::Path;
 
 //fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 fn main() -> Result<()> {
     let cli = cli::parse();
+    let result = run(&cli);
+
+    // Failures of the unattended runs, which nobody may be watching
+    let unattended = match &cli.command {
+        None => cli.output == cli::Output::Mail,
+        Some(cli::Command::Agent) => true,
+        #[cfg(feature = "daemon")]
+        Some(cli::Command::Daemon) => true,
+        _ => false,
+    };
+
+    if let Err(err) = &result {
+        if unattended {
+            notify_failure(&cli, err);
+        }
+    }
+
+    result
+}
+
+fn run(cli: &cli::Cli) -> Result<()> {
+    if let Some(root) = &cli.root {
+        apt::set_root(root)?;
+    }
 
     match &cli.command {
         Some(command) => match command {
-            cli::Command::Configure => cli::commands::configure(&cli),
+            cli::Command::Configure => cli::commands::configure(cli),
+            cli::Command::Hook { hook } => match hook {
+                cli::Hook::PreDpkg => cli::commands::hook_pre_dpkg(cli),
+                cli::Hook::PostDpkg => cli::commands::hook_post_dpkg(cli),
+            },
+            cli::Command::Agent => agent(cli),
+            cli::Command::Flush { due } => flush(cli, *due),
+            #[cfg(feature = "serve")]
+            cli::Command::Serve { listen } => serve(cli, listen),
+            #[cfg(feature = "daemon")]
+            cli::Command::Daemon => daemon(cli),
+            #[cfg(feature = "collector")]
+            cli::Command::Collector { command } => match command {
+                cli::CollectorCommand::Serve { listen } => {
+                    cli::commands::collector_serve(cli, listen)
+                }
+                cli::CollectorCommand::Digest => cli::commands::collector_digest(cli),
+            },
+            cli::Command::Ack(args) => match &args.package {
+                Some(package) if args.remove => cli::commands::ack_remove(cli, package),
+                Some(package) => cli::commands::ack_add(cli, package, args),
+                None => cli::commands::ack_list(cli),
+            },
+            cli::Command::Sources { command } => match command {
+                cli::SourcesCommand::Audit => cli::commands::sources_audit(cli),
+            },
         },
         None => {
-            let config = config::Config::from_file(&cli.config)?;
+            let config = match cli.output {
+                cli::Output::Json => config::Config::from_file(&cli.config).unwrap_or_default(),
+                cli::Output::Mail => config::Config::from_file(&cli.config)?,
+            };
 
-            let output = apt::update()?;
+            if cli.output == cli::Output::Mail {
+                retry_outbox(&config, false);
+            }
 
-            let packages = apt::list_upgradeable()?;
+            if cli.root.is_none() && !config.targets.is_empty() {
+                return notify_targets(cli, &config);
+            }
+
+            // Roots are checked offline, against the package lists they contain
+            let output = match cli.root {
+                Some(_) => Vec::new(),
+                None => apt::update()?,
+            };
+
+            let report = build_report(&config, output, cli.output == cli::Output::Json)?;
+
+            send_report(cli, &config, &report)
+        }
+    }
+}
 
-            if packages.len() == 0 {
-                return Ok(());
+/// Prints the report, or emails it if there are unacknowledged updates.
+fn send_report(cli: &cli::Cli, config: &config::Config, report: &report::Report) -> Result<()> {
+    if cli.output == cli::Output::Json {
+        println!("{}", report.to_json()?);
+        return Ok(());
+    }
+
+    if let Some(summary) = &config.summary {
+        return send_summary(config, summary, report);
+    }
+
+    let repository_problems =
+        config.repositories.notify_without_updates && !report.repositories.is_empty();
+
+    // A notification queued during quiet hours would be out of date
+    if report.unacknowledged().next().is_none() && !repository_problems {
+        return mail::discard_queued(config);
+    }
+
+    let message = mail::build_message(config, report)?;
+    let security = report.unacknowledged().any(|pkg| pkg.is_security());
+
+    print_delivery(mail::deliver(config, message, security, !report.errors.is_empty())?);
+
+    Ok(())
+}
+
+/// Records the report, and emails the summary of the recorded ones when it's due.
+fn send_summary(
+    config: &config::Config,
+    summary: &summary::Summary,
+    report: &report::Report,
+) -> Result<()> {
+    let dir = config.state.dir();
+    summary.record(&dir, report)?;
+
+    let Some(period) = summary.due(&dir, Utc::now())? else {
+        return Ok(());
+    };
+
+    if !period.is_empty() {
+        let message = mail::build_summary_message(config, summary, &period)?;
+        let security = period.pending.iter().any(|pkg| pkg.security);
+
+        match mail::deliver(config, message, security, period.error_runs > 0) {
+            Ok(delivery) => print_delivery(delivery),
+            // Retried from the outbox, rather than summarized again by the next run
+            Err(err) if err.is_kept_in_outbox() => {
+                summary.clear(&dir)?;
+                return Err(err);
             }
+            Err(err) => return Err(err),
+        }
+    }
+
+    summary.clear(&dir)
+}
+
+fn print_delivery(delivery: mail::Delivery) {
+    match delivery {
+        mail::Delivery::Sent(response) if !response.is_positive() => {
+            println!("Response: {:?}", response)
+        }
+        mail::Delivery::Sent(_) => {}
+        mail::Delivery::Queued(until) => println!(
+            "Quiet hours, notification queued until {}",
+            until.with_timezone(&Local)
+        ),
+    }
+}
+
+/// Checks each of the configured targets and sends a single combined notification.
+///
+/// Summaries are per host, so `[summary]` can't be used with targets.
+fn notify_targets(cli: &cli::Cli, config: &config::Config) -> Result<()> {
+    if config.summary.is_some() && cli.output == cli::Output::Mail {
+        return Err(Error::Config(::config::ConfigError::Message(String::from(
+            "[summary] can't be used with [[targets]]",
+        ))));
+    }
+
+    let combined = report::Combined::new(collect_targets(
+        config,
+        cli.output == cli::Output::Json,
+    )?);
+
+    if cli.output == cli::Output::Json {
+        println!("{}", combined.to_json()?);
+        return Ok(());
+    }
+
+    let repository_problems = config.repositories.notify_without_updates
+        && combined
+            .targets
+            .iter()
+            .any(|report| !report.repositories.is_empty());
+
+    if combined.unacknowledged().next().is_none() && !repository_problems {
+        return mail::discard_queued(config);
+    }
+
+    let message = mail::build_combined_message(config, &combined)?;
+    let security = combined.unacknowledged().any(|pkg| pkg.is_security());
+    let errors = combined.targets.iter().any(|report| !report.errors.is_empty());
+
+    print_delivery(mail::deliver(config, message, security, errors)?);
+
+    Ok(())
+}
+
+/// Sends the `[on_error]` notification about `err`, if configured.
+fn notify_failure(cli: &cli::Cli, err: &Error) {
+    // The config file may be what failed
+    let config = match config::Config::from_file(&cli.config) {
+        Ok(config) => config,
+        Err(_) => match config::Config::notification_sections(&cli.config) {
+            Ok(config) => config,
+            Err(_) => return,
+        },
+    };
+
+    if let Some(on_error) = &config.on_error {
+        if let Err(notify_err) = on_error.notify(&config, err) {
+            eprintln!("Notifying about the failure: {}", notify_err);
+        }
+    }
+}
+
+/// Retries the notifications in the outbox (all of them, unless `due`), and sends the one
+/// queued during quiet hours if they are over.
+fn flush(cli: &cli::Cli, due: bool) -> Result<()> {
+    let config = config::Config::from_file(&cli.config)?;
+
+    retry_outbox(&config, !due);
+
+    if let Some(response) = mail::flush_queued(&config)? {
+        print_delivery(mail::Delivery::Sent(response));
+    }
+
+    Ok(())
+}
+
+/// Retries the notifications in the outbox that are due, or all of them if `force`.
+///
+/// Errors are printed, so that they don't prevent the run from sending a new notification.
+fn retry_outbox(config: &config::Config, force: bool) {
+    let retried = match config.outbox.retry(config, &config.state.dir(), force) {
+        Ok(retried) => retried,
+        Err(err) => return eprintln!("Outbox: {}", err),
+    };
+
+    for dropped in &retried.dropped {
+        eprintln!("Outbox: dropping notification {}", dropped);
+    }
 
-            let message = mail::build_message(&config, &packages, &output)?;
-            let response = mail::send_smtp(&config, message)?;
+    for path in &retried.quarantined {
+        eprintln!("Outbox: couldn't read a notification, moved to {}", path.display());
+    }
+
+    if retried.sent > 0 || !retried.dropped.is_empty() {
+        println!(
+            "Outbox: {} sent, {} dropped, {} left",
+            retried.sent,
+            retried.dropped.len(),
+            retried.pending
+        );
+    }
+}
+
+/// Sends the report, or a report per target, to the collector.
+fn agent(cli: &cli::Cli) -> Result<()> {
+    let config = config::Config::from_file(&cli.config)?;
+
+    let Some(agent) = &config.agent else {
+        return Err(Error::Config(::config::ConfigError::NotFound(String::from("agent"))));
+    };
+
+    let reports = match &cli.root {
+        Some(_) => vec![build_report(&config, Vec::new(), false)?],
+        None if config.targets.is_empty() => vec![build_report(&config, apt::update()?, false)?],
+        None => collect_targets(&config, false)?,
+    };
+
+    let reports = reports
+        .iter()
+        .map(|report| report.to_json())
+        .collect::<serde_json::Result<Vec<String>>>()?;
+
+    // The spool belongs to the host, not to the root being checked
+    let spool_dir = match &cli.root {
+        Some(_) => {
+            apt::set_root(Path::new("/"))?;
+            config.state.dir()
+        }
+        None => config.state.dir(),
+    };
+
+    let shipped = agent.ship(&spool_dir, reports)?;
+
+    if let Some(err) = &shipped.error {
+        eprintln!("Shipping reports: {}", err);
+    }
+
+    for path in &shipped.quarantined {
+        eprintln!(
+            "Shipping reports: couldn't read a spooled report, moved to {}",
+            path.display()
+        );
+    }
+
+    if shipped.spooled > 0 || shipped.rejected > 0 {
+        println!(
+            "{} report(s) sent, {} spooled for the next run, {} rejected by the collector",
+            shipped.sent, shipped.spooled, shipped.rejected
+        );
+    }
 
-            if !response.is_positive() {
-                println!("Response: {:?}", response);
+    Ok(())
+}
+
+/// Serves the report of the host (or `--root`), refreshed in the background.
+#[cfg(feature = "serve")]
+fn serve(cli: &cli::Cli, listen: &Option<String>) -> Result<()> {
+    let config = config::Config::from_file(&cli.config)?;
+
+    let mut status = config.serve.clone();
+    if let Some(listen) = listen {
+        status.listen = listen.clone();
+    }
+
+    status.serve(&config, || {
+        let output = match cli.root {
+            Some(_) => Vec::new(),
+            None => apt::update()?,
+        };
+
+        build_report(&config, output, false)
+    })
+}
+
+/// Checks the host (or `--root`) on the configured schedule, and when its package lists or
+/// installed packages change.
+///
+/// Only scheduled checks update the package lists. A change only sends a notification when
+/// it brings updates that weren't in the previous one, so that installing packages doesn't.
+#[cfg(feature = "daemon")]
+fn daemon(cli: &cli::Cli) -> Result<()> {
+    let config = config::Config::from_file(&cli.config)?;
+    let mut notified: BTreeSet<(String, Option<String>)> = BTreeSet::new();
+
+    // The end of the quiet hours, or the next retry of the outbox
+    let wake = || {
+        let quiet_until = config.schedule.quiet_until(Utc::now()).ok().flatten();
+        let next_attempt = config.outbox.next_attempt(&config.state.dir()).ok().flatten();
+
+        quiet_until
+            .into_iter()
+            .chain(next_attempt)
+            .min()
+            .map(|wake| wake.with_timezone(&Local))
+    };
+
+    config.daemon.run(wake, |trigger| {
+        if let Err(err) = daemon_check(cli, &config, trigger, &mut notified) {
+            eprintln!("Check ({:?}): {}", trigger, err);
+            notify_failure(cli, &err);
+        }
+    })
+}
+
+/// One check of the daemon. `notified` holds the updates of the previous notification.
+#[cfg(feature = "daemon")]
+fn daemon_check(
+    cli: &cli::Cli,
+    config: &config::Config,
+    trigger: Trigger,
+    notified: &mut BTreeSet<(String, Option<String>)>,
+) -> Result<()> {
+    retry_outbox(config, false);
+
+    if trigger == Trigger::Wake {
+        if let Some(response) = mail::flush_queued(config)? {
+            print_delivery(mail::Delivery::Sent(response));
+        }
+        return Ok(());
+    }
+
+    let output = match (trigger, &cli.root) {
+        (Trigger::Schedule, None) => apt::update()?,
+        _ => Vec::new(),
+    };
+
+    let report = build_report(config, output, false)?;
+    let pending: BTreeSet<(String, Option<String>)> = report
+        .unacknowledged()
+        .map(|pkg| (pkg.name.clone(), pkg.candidate.clone()))
+        .collect();
+
+    let new = !pending.is_subset(notified);
+    *notified = pending;
+
+    match trigger {
+        Trigger::Changed if !new => Ok(()),
+        _ => send_report(cli, config, &report),
+    }
+}
+
+/// Checks each of the configured targets.
+///
+/// Only the host's package lists are updated. Each target (the host included) is checked with
+/// its own apt configuration, read again by `apt::set_root`. A target that can't be checked
+/// gets a report containing the error.
+fn collect_targets(config: &config::Config, read_only: bool) -> Result<Vec<report::Report>> {
+    let mut reports = Vec::new();
+
+    for target in &config.targets {
+        let result = target.root().and_then(|root| {
+            apt::set_root(root.as_deref().unwrap_or(Path::new("/")))?;
+
+            let output = match root {
+                Some(_) => Vec::new(),
+                None => apt::update()?,
+            };
+
+            build_report(config, output, read_only)
+        });
+
+        let mut report = result.unwrap_or_else(|err| {
+            report::Report::new(
+                Vec::new(),
+                vec![apt::ProgressOutput::Error(apt::OutputError::Error(
+                    err.to_string(),
+                ))],
+            )
+        });
+
+        if let Some(name) = target.name() {
+            report.host = Some(name);
+        }
+
+        reports.push(report);
+    }
+
+    apt::set_root(Path::new("/"))?;
+
+    Ok(reports)
+}
+
+/// Lists, filters and annotates the upgradeable packages of the current root.
+///
+/// The state (e.g. when updates were first seen) isn't saved if `read_only`.
+fn build_report(
+    config: &config::Config,
+    output: Vec<apt::ProgressOutput>,
+    read_only: bool,
+) -> Result<report::Report> {
+    let today = Utc::now().date_naive();
+
+    // Tracked before filtering, so that a package doesn't lose its age while a rule
+    // leaves it out
+    let mut packages = apt::list_upgradeable()?;
+    state::track_pending(&config.state.dir(), &mut packages, read_only)?;
+
+    let mut filtered = config.filter.apply(packages, today)?;
+
+    // Optional: the report is still sent without CVEs
+    if let Some(feed) = &config.cve {
+        if let Err(err) = feed.refresh() {
+            eprintln!("Couldn't download the CVE data: {}", err);
+        }
+
+        match feed.load() {
+            Ok(database) => {
+                database.enrich(&mut filtered.packages);
+
+                if feed.sort {
+                    cve::sort_by_severity(&mut filtered.packages);
+                }
             }
+            Err(err) => eprintln!("Not listing CVEs: {}", err),
+        }
+    }
 
-            Ok(())
+    config.news.collect(&mut filtered.packages)?;
+
+    let acks = ack::Acknowledgements::load(&config.state.dir())?;
+    acks.apply(&mut filtered.packages, today);
+    acks.apply(&mut filtered.held, today);
+
+    let mut report = report::Report::new(filtered.packages, output);
+    report.held = filtered.held;
+    report.suppressed = filtered.suppressed;
+
+    // Optional: the report is still sent without the audit
+    if config.sources.report {
+        match config.sources.run(today) {
+            Ok(findings) => report.sources = findings,
+            Err(err) => eprintln!("Not auditing the sources: {}", err),
         }
     }
+
+    Ok(report)
 }
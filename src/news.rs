@@ -0,0 +1,348 @@
+use crate::{apt::Upgradeable, error::Result};
+use flate2::read::GzDecoder;
+use rust_apt::{config::Config, util::cmp_versions};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Where `NEWS.Debian` entries of pending upgrades are read from.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Sources {
+    /// The candidate's `.deb`, if it has already been downloaded to the apt cache
+    pub cache: bool,
+    /// A server publishing the `NEWS.Debian` files, at `uri`
+    pub server: bool,
+    /// The server URI; `@CHANGEPATH@` is replaced as in `Acquire::Changelogs::URI`
+    pub uri: Option<String>,
+}
+
+impl Default for Sources {
+    fn default() -> Sources {
+        Sources {
+            cache: true,
+            server: false,
+            uri: None,
+        }
+    }
+}
+
+/// An entry of a source package's `NEWS.Debian`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub source: String,
+    pub version: String,
+    /// The entry as written, including the header and trailer lines
+    pub text: String,
+}
+
+impl Sources {
+    /// Sets `news` on each package to the entries newer than the installed version.
+    ///
+    /// Packages without a cached `.deb` or a `NEWS.Debian` on the server (or which it can't
+    /// be fetched from) are skipped.
+    pub fn collect(&self, packages: &mut [Upgradeable]) -> Result<()> {
+        if !self.cache && !self.server {
+            return Ok(());
+        }
+
+        let archives =
+            PathBuf::from(Config::new().dir("Dir::Cache::archives", "/var/cache/apt/archives/"));
+
+        self.collect_from(&archives, packages);
+        Ok(())
+    }
+
+    fn collect_from(&self, archives: &Path, packages: &mut [Upgradeable]) {
+        // Binary packages built from the same source share the same NEWS.Debian, but only
+        // some of their .debs may be cached, so each is tried until one has it
+        let mut by_source: HashMap<(String, String), Vec<Entry>> = HashMap::new();
+        let mut fetched: HashSet<(String, String)> = HashSet::new();
+
+        for pkg in packages.iter() {
+            let (Some(_), Some(candidate)) = (&pkg.installed, &pkg.candidate) else {
+                continue;
+            };
+
+            let key = (pkg.source_name.clone(), pkg.source_version.clone());
+
+            if !by_source.contains_key(&key) {
+                let mut text = None;
+
+                if self.cache {
+                    let deb = archives.join(format!(
+                        "{}_{}_{}.deb",
+                        pkg.name,
+                        candidate.replace(':', "%3a"),
+                        pkg.arch
+                    ));
+                    text = from_deb(&deb, &pkg.name);
+                }
+
+                // The server has the same file for every binary package
+                if text.is_none() && self.server && fetched.insert(key.clone()) {
+                    let component = component(pkg.section.as_deref().unwrap_or_default());
+
+                    if let Some(uri) = self.server_uri(component, &key.0, &key.1) {
+                        text = fetch(&uri).ok().flatten();
+                    }
+                }
+
+                if let Some(text) = text {
+                    by_source.insert(key.clone(), parse(&key.0, &text));
+                }
+            }
+        }
+
+        for pkg in packages.iter_mut() {
+            let Some(installed) = &pkg.installed else {
+                continue;
+            };
+            let key = (pkg.source_name.clone(), pkg.source_version.clone());
+
+            pkg.news = by_source
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .filter(|entry| {
+                    cmp_versions(installed, &entry.version) == Ordering::Less
+                        && cmp_versions(&entry.version, &pkg.source_version) != Ordering::Greater
+                })
+                .cloned()
+                .collect();
+        }
+    }
+
+    /// The configured `uri` with `@CHANGEPATH@` replaced, e.g. `main/a/apt/apt_2.6.1`.
+    ///
+    /// There's no default: the changelog servers of Debian and Ubuntu don't publish
+    /// `NEWS.Debian` files.
+    fn server_uri(&self, component: &str, source: &str, version: &str) -> Option<String> {
+        let template = self.uri.as_ref()?;
+
+        let prefix = if source.starts_with("lib") {
+            source.get(..4).unwrap_or(source)
+        } else {
+            source.get(..1).unwrap_or(source)
+        };
+        let version = version.split_once(':').map_or(version, |(_, v)| v);
+
+        Some(template.replace(
+            "@CHANGEPATH@",
+            &format!("{}/{}/{}/{}_{}", component, prefix, source, source, version),
+        ))
+    }
+}
+
+/// The archive component of a section, e.g. `contrib` for `contrib/games` and `main` for `libs`.
+fn component(section: &str) -> &str {
+    section
+        .split_once('/')
+        .map_or("main", |(component, _)| component)
+}
+
+/// Extracts `/usr/share/doc/<package>/NEWS.Debian.gz` from a `.deb`.
+fn from_deb(deb: &Path, package: &str) -> Option<String> {
+    if !deb.exists() {
+        return None;
+    }
+
+    let mut dpkg_deb = Command::new("dpkg-deb")
+        .arg("--fsys-tarfile")
+        .arg(deb)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    let tar = Command::new("tar")
+        .arg("-xOf")
+        .arg("-")
+        .arg(format!("./usr/share/doc/{}/NEWS.Debian.gz", package))
+        .stdin(dpkg_deb.stdout.take()?)
+        .stderr(Stdio::null())
+        .output();
+
+    dpkg_deb.wait().ok()?;
+
+    let compressed = tar.ok()?.stdout;
+    if compressed.is_empty() {
+        return None;
+    }
+
+    let mut text = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut text)
+        .ok()?;
+
+    Some(text)
+}
+
+/// Downloads a plain text `NEWS.Debian`, returning `None` if the server doesn't have one.
+#[cfg(feature = "http")]
+fn fetch(uri: &str) -> Result<Option<String>> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(std::time::Duration::from_secs(5))
+        .timeout(std::time::Duration::from_secs(15))
+        .build();
+
+    match agent.get(uri).call() {
+        Ok(response) => Ok(Some(response.into_string()?)),
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(err) => Err(crate::error::Error::Http(err.to_string())),
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch(_uri: &str) -> Result<Option<String>> {
+    Ok(None)
+}
+
+/// Splits a `NEWS.Debian` file, which uses the changelog format, into its entries.
+fn parse(source: &str, text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+
+    for line in text.lines() {
+        if let Some((version, lines)) = current.as_mut() {
+            lines.push(line);
+
+            if line.starts_with(" -- ") {
+                entries.push(Entry {
+                    source: source.to_string(),
+                    version: version.clone(),
+                    text: lines.join("\n"),
+                });
+                current = None;
+            }
+        } else if !line.starts_with(char::is_whitespace) {
+            // e.g. "apt (2.1.8) unstable; urgency=medium"
+            let version = line
+                .split_once(" (")
+                .and_then(|(_, rest)| rest.split_once(')'))
+                .map(|(version, _)| version.to_string());
+
+            if let Some(version) = version {
+                current = Some((version, vec![line]));
+            }
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEWS: &str = "\
+apt (2.1.8) unstable; urgency=medium
+
+  apt(8) now waits for the lock.
+
+ -- Julian Andres Klode <jak@debian.org>  Wed, 05 Aug 2020 12:00:00 +0200
+
+apt (1.1~exp9) experimental; urgency=medium
+
+  The new \"apt\" binary.
+
+ -- Michael Vogt <mvo@debian.org>  Thu, 20 Aug 2015 12:00:00 +0200
+";
+
+    #[test]
+    fn parse_entries() {
+        let entries = parse("apt", NEWS);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].version, "2.1.8");
+        assert_eq!(entries[1].version, "1.1~exp9");
+        assert!(entries[0].text.starts_with("apt (2.1.8) unstable"));
+        assert!(entries[0].text.ends_with("+0200"));
+        assert!(entries[0].text.contains("waits for the lock"));
+    }
+
+    #[test]
+    fn server_uri_only_when_configured() {
+        let mut sources = Sources {
+            server: true,
+            ..Default::default()
+        };
+        assert_eq!(sources.server_uri("main", "apt", "2.6.1"), None);
+
+        sources.uri = Some(String::from(
+            "https://news.example.com/@CHANGEPATH@/NEWS.Debian",
+        ));
+        assert_eq!(
+            sources
+                .server_uri("main", "libxml2", "1:2.9.14+dfsg-1.3")
+                .as_deref(),
+            Some("https://news.example.com/main/libx/libxml2/libxml2_2.9.14+dfsg-1.3/NEWS.Debian")
+        );
+    }
+
+    #[test]
+    fn component_of_section() {
+        assert_eq!(component("contrib/games"), "contrib");
+        assert_eq!(component("non-free-firmware/kernel"), "non-free-firmware");
+        assert_eq!(component("libs"), "main");
+        assert_eq!(component(""), "main");
+    }
+
+    #[test]
+    fn server_is_off_by_default() {
+        assert!(!Sources::default().server);
+    }
+
+    #[test]
+    fn news_from_any_cached_binary_of_the_source() {
+        let archives =
+            std::env::temp_dir().join(format!("apt-updates-notifier-news-{}", std::process::id()));
+        let staging = archives.join("staging");
+        let doc = staging.join("usr/share/doc/libapt-pkg6.0");
+        std::fs::create_dir_all(&doc).unwrap();
+        std::fs::create_dir_all(staging.join("DEBIAN")).unwrap();
+        std::fs::write(
+            staging.join("DEBIAN/control"),
+            "Package: libapt-pkg6.0\nVersion: 2.1.8\nArchitecture: amd64\n\
+             Maintainer: APT <deity@lists.debian.org>\nDescription: Fixture\n",
+        )
+        .unwrap();
+
+        let mut news = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut news, NEWS.as_bytes()).unwrap();
+        std::fs::write(doc.join("NEWS.Debian.gz"), news.finish().unwrap()).unwrap();
+
+        let built = Command::new("dpkg-deb")
+            .args(["--root-owner-group", "--build"])
+            .arg(&staging)
+            .arg(archives.join("libapt-pkg6.0_2.1.8_amd64.deb"))
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(built.success());
+
+        // Only the second binary package's .deb is in the cache
+        let mut packages = ["apt", "libapt-pkg6.0", "apt-utils"].map(|name| Upgradeable {
+            name: name.to_string(),
+            installed: Some(String::from("2.0.9")),
+            candidate: Some(String::from("2.1.8")),
+            arch: String::from("amd64"),
+            source_name: String::from("apt"),
+            source_version: String::from("2.1.8"),
+            ..Default::default()
+        });
+
+        Sources::default().collect_from(&archives, &mut packages);
+        std::fs::remove_dir_all(&archives).unwrap();
+
+        for pkg in &packages {
+            let versions: Vec<&str> = pkg.news.iter().map(|e| e.version.as_str()).collect();
+            assert_eq!(versions, ["2.1.8"], "{}", pkg.name);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    mail::Stored,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const OUTBOX_DIR: &str = "outbox";
+
+/// Notifications that couldn't be sent, retried by later runs with exponential backoff.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Outbox {
+    /// Minutes before the first retry, doubled after each failed one
+    pub retry_minutes: i64,
+    /// Notifications still not sent after this long are dropped
+    pub max_age_hours: i64,
+}
+
+impl Default for Outbox {
+    fn default() -> Outbox {
+        Outbox {
+            retry_minutes: 5,
+            max_age_hours: 72,
+        }
+    }
+}
+
+/// A notification waiting in the outbox.
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    created: DateTime<Utc>,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+    /// Why the last attempt failed
+    error: String,
+    message: Stored,
+}
+
+/// Outcome of [`Outbox::retry`].
+#[derive(Default)]
+pub struct Retried {
+    pub sent: usize,
+    /// Still in the outbox
+    pub pending: usize,
+    /// Too old, dropped without being sent, e.g. `"Subject" from 2024-05-01 06:00, not sent
+    /// after 9 attempt(s): <error>`
+    pub dropped: Vec<String>,
+    /// Entries that couldn't be read, renamed to `.corrupt` so that they aren't retried again
+    pub quarantined: Vec<PathBuf>,
+}
+
+impl Outbox {
+    /// Keeps `message`, which couldn't be sent because of `error`, in the outbox in `dir`.
+    pub fn add(&self, dir: &Path, message: Stored, error: &Error) -> Result<()> {
+        let now = Utc::now();
+        let entry = Entry {
+            created: now,
+            attempts: 1,
+            next_attempt: now + self.backoff(1),
+            error: error.to_string(),
+            message,
+        };
+
+        let outbox = dir.join(OUTBOX_DIR);
+        fs::create_dir_all(&outbox)?;
+
+        let mut path = outbox.join(format!("{}.json", now.format("%Y%m%dT%H%M%S%.f")));
+        for i in 1.. {
+            if !path.exists() {
+                break;
+            }
+            path = outbox.join(format!("{}-{}.json", now.format("%Y%m%dT%H%M%S%.f"), i));
+        }
+
+        fs::write(path, serde_json::to_string_pretty(&entry)?)?;
+
+        Ok(())
+    }
+
+    /// Sends the notifications in the outbox in `dir` whose next attempt is due, or all of
+    /// them if `force`. Those older than `max_age_hours` are dropped and logged instead.
+    pub fn retry(&self, config: &Config, dir: &Path, force: bool) -> Result<Retried> {
+        let now = Utc::now();
+        let mut retried = Retried::default();
+
+        for path in entries(dir)? {
+            let Ok(mut entry) = serde_json::from_str::<Entry>(&fs::read_to_string(&path)?) else {
+                let corrupt = path.with_extension("corrupt");
+                fs::rename(&path, &corrupt)?;
+                retried.quarantined.push(corrupt);
+                continue;
+            };
+
+            if now - entry.created > Duration::hours(self.max_age_hours) {
+                fs::remove_file(&path)?;
+                retried.dropped.push(format!(
+                    "\"{}\" from {}, not sent after {} attempt(s): {}",
+                    entry.message.subject(),
+                    entry.created.format("%Y-%m-%d %H:%M"),
+                    entry.attempts,
+                    entry.error
+                ));
+                continue;
+            }
+
+            if !force && entry.next_attempt > now {
+                retried.pending += 1;
+                continue;
+            }
+
+            match entry.message.send(config) {
+                Ok(_) => {
+                    fs::remove_file(&path)?;
+                    retried.sent += 1;
+                }
+                Err(err) => {
+                    entry.attempts += 1;
+                    entry.next_attempt = now + self.backoff(entry.attempts);
+                    entry.error = err.to_string();
+                    fs::write(&path, serde_json::to_string_pretty(&entry)?)?;
+                    retried.pending += 1;
+                }
+            }
+        }
+
+        Ok(retried)
+    }
+
+    /// When the next notification in the outbox in `dir` is due to be retried.
+    ///
+    /// Entries that can't be read are left to [`Outbox::retry`] to quarantine.
+    pub fn next_attempt(&self, dir: &Path) -> Result<Option<DateTime<Utc>>> {
+        let mut next: Option<DateTime<Utc>> = None;
+
+        for path in entries(dir)? {
+            let Ok(entry) = serde_json::from_str::<Entry>(&fs::read_to_string(&path)?) else {
+                continue;
+            };
+            next = Some(next.map_or(entry.next_attempt, |next| next.min(entry.next_attempt)));
+        }
+
+        Ok(next)
+    }
+
+    /// Delay before the attempt following `attempts` failed ones.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let delay = Duration::minutes(self.retry_minutes * 2_i64.pow(attempts.min(16) - 1));
+        delay.min(Duration::hours(self.max_age_hours))
+    }
+}
+
+/// The entries in the outbox, oldest first.
+fn entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let outbox = dir.join(OUTBOX_DIR);
+
+    if !outbox.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(outbox)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    entries.sort();
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outbox_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-outbox-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(OUTBOX_DIR)).unwrap();
+        dir
+    }
+
+    fn write_entry(dir: &Path, name: &str, created: DateTime<Utc>, next_attempt: DateTime<Utc>) {
+        let entry = serde_json::json!({
+            "created": created,
+            "attempts": 2,
+            "next_attempt": next_attempt,
+            "error": "SMTP Error: connection refused",
+            "message": { "from": null, "to": [], "subject": "2 update(s)", "message": "" },
+        });
+        fs::write(dir.join(OUTBOX_DIR).join(name), entry.to_string()).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_age() {
+        let outbox = Outbox {
+            retry_minutes: 5,
+            max_age_hours: 1,
+        };
+
+        assert_eq!(outbox.backoff(1), Duration::minutes(5));
+        assert_eq!(outbox.backoff(2), Duration::minutes(10));
+        assert_eq!(outbox.backoff(4), Duration::minutes(40));
+        assert_eq!(outbox.backoff(5), Duration::hours(1));
+        assert_eq!(outbox.backoff(u32::MAX), Duration::hours(1));
+    }
+
+    #[test]
+    fn corrupt_entries_are_quarantined() {
+        let dir = outbox_dir("corrupt");
+        let now = Utc::now();
+        fs::write(dir.join(OUTBOX_DIR).join("1.json"), "{ truncated").unwrap();
+        write_entry(&dir, "2.json", now, now + Duration::minutes(10));
+        let outbox = Outbox::default();
+
+        assert_eq!(
+            outbox.next_attempt(&dir).unwrap(),
+            Some(now + Duration::minutes(10))
+        );
+
+        let retried = outbox.retry(&Config::default(), &dir, false).unwrap();
+
+        assert_eq!(
+            retried.quarantined,
+            vec![dir.join(OUTBOX_DIR).join("1.corrupt")]
+        );
+        assert_eq!((retried.sent, retried.pending), (0, 1));
+        assert!(dir.join(OUTBOX_DIR).join("1.corrupt").exists());
+        assert_eq!(entries(&dir).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn old_entries_are_dropped() {
+        let dir = outbox_dir("old");
+        let created = Utc::now() - Duration::hours(73);
+        write_entry(&dir, "1.json", created, created + Duration::hours(72));
+
+        let retried = Outbox::default()
+            .retry(&Config::default(), &dir, true)
+            .unwrap();
+
+        assert_eq!(retried.dropped.len(), 1);
+        assert!(retried.dropped[0].starts_with("\"2 update(s)\" from "));
+        assert!(retried.dropped[0].ends_with("after 2 attempt(s): SMTP Error: connection refused"));
+        assert!(entries(&dir).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use crate::util;
+use rust_apt::config::Config;
+use std::{fs, os::unix::fs::MetadataExt};
+
+const MACHINE_ID: &str = "/etc/machine-id";
+
+/// apt's phased update settings, read from the apt configuration.
+pub struct Policy {
+    always_include: bool,
+    never_include: bool,
+    machine_id: String,
+    /// apt includes phased updates in chroots and reproducible builds (`SOURCE_DATE_EPOCH`)
+    exempt: bool,
+}
+
+impl Policy {
+    pub fn from_apt_config() -> Self {
+        let config = Config::new();
+        let flag = |key: &str| {
+            config.bool(
+                &format!("APT::Get::{}", key),
+                config.bool(&format!("Update-Manager::{}", key), false),
+            )
+        };
+
+        Self {
+            always_include: flag("Always-Include-Phased-Updates"),
+            never_include: flag("Never-Include-Phased-Updates"),
+            machine_id: config
+                .get("APT::Machine-ID")
+                .unwrap_or_else(|| {
+                    fs::read_to_string(util::in_root(MACHINE_ID)).unwrap_or_default()
+                })
+                .trim()
+                .to_string(),
+            exempt: std::env::var_os("SOURCE_DATE_EPOCH").is_some() || is_chroot(),
+        }
+    }
+
+    /// Whether apt holds back an update phased to `percentage` percent of machines on this machine.
+    ///
+    /// Security updates are never phased.
+    pub fn defers(
+        &self,
+        source_name: &str,
+        source_version: &str,
+        percentage: u32,
+        security: bool,
+    ) -> bool {
+        if self.always_include || security || percentage >= 100 {
+            return false;
+        }
+
+        if self.never_include {
+            return true;
+        }
+
+        // Without a machine ID apt includes phased updates
+        if self.machine_id.is_empty() || self.exempt {
+            return false;
+        }
+
+        let seed = format!("{}-{}-{}", source_name, source_version, self.machine_id);
+        bucket(seed.as_bytes()) > percentage
+    }
+}
+
+/// Whether `/` isn't the root of PID 1, as apt checks it.
+fn is_chroot() -> bool {
+    match (fs::metadata("/"), fs::metadata("/proc/1/root")) {
+        (Ok(root), Ok(init_root)) => root.dev() != init_root.dev() || root.ino() != init_root.ino(),
+        _ => false,
+    }
+}
+
+/// apt's `std::uniform_int_distribution<unsigned>(0, 100)` over a `std::minstd_rand`
+/// seeded with a `std::seed_seq` of `seed`, as implemented by libstdc++.
+fn bucket(seed: &[u8]) -> u32 {
+    const MODULUS: u64 = 2147483647;
+
+    let words = seed_seq(seed, 4);
+    let state = u64::from(words[3]) % MODULUS;
+    let mut state = if state == 0 { 1 } else { state };
+
+    // The engine's range is [1, MODULUS - 1]
+    let range = MODULUS - 2;
+    let scaling = range / 101;
+    let past = scaling * 101;
+
+    loop {
+        state = state * 48271 % MODULUS;
+        let value = state - 1;
+
+        if value < past {
+            return (value / scaling) as u32;
+        }
+    }
+}
+
+/// `std::seed_seq::generate`, filling `n` words.
+fn seed_seq(seed: &[u8], n: usize) -> Vec<u32> {
+    let t = |x: u32| x ^ (x >> 27);
+    let s = seed.len();
+    let step = if n >= 623 {
+        11
+    } else if n >= 68 {
+        7
+    } else if n >= 39 {
+        5
+    } else if n >= 7 {
+        3
+    } else {
+        (n - 1) / 2
+    };
+    let p = (n - step) / 2;
+    let q = p + step;
+    let m = (s + 1).max(n);
+
+    let mut words = vec![0x8b8b8b8bu32; n];
+
+    for k in 0..m {
+        let r1 = 1664525u32.wrapping_mul(t(words[k % n]
+            ^ words[(k + p) % n]
+            ^ words[(k + n - 1) % n]));
+        let r2 = r1.wrapping_add(if k == 0 {
+            s as u32
+        } else if k <= s {
+            (k % n) as u32 + u32::from(seed[k - 1])
+        } else {
+            (k % n) as u32
+        });
+
+        words[(k + p) % n] = words[(k + p) % n].wrapping_add(r1);
+        words[(k + q) % n] = words[(k + q) % n].wrapping_add(r2);
+        words[k % n] = r2;
+    }
+
+    for k in m..m + n {
+        let r3 = 1566083941u32.wrapping_mul(t(words[k % n]
+            .wrapping_add(words[(k + p) % n])
+            .wrapping_add(words[(k + n - 1) % n])));
+        let r4 = r3.wrapping_sub((k % n) as u32);
+
+        words[(k + p) % n] ^= r3;
+        words[(k + q) % n] ^= r4;
+        words[k % n] = r4;
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MACHINE_ID: &str = "0123456789abcdef0123456789abcdef";
+
+    fn policy() -> Policy {
+        Policy {
+            always_include: false,
+            never_include: false,
+            machine_id: MACHINE_ID.to_string(),
+            exempt: false,
+        }
+    }
+
+    // Reference values from libstdc++'s seed_seq, minstd_rand and uniform_int_distribution,
+    // seeded as apt's ExcludePhased() does
+    #[test]
+    fn bucket_matches_libstdcxx() {
+        let cases = [
+            ("apt-2.4.11-0123456789abcdef0123456789abcdef", 73),
+            (
+                "openssl-3.0.2-0ubuntu1.15-0123456789abcdef0123456789abcdef",
+                50,
+            ),
+            (
+                "systemd-249.11-0ubuntu3.12-e4b3c1d2a5f60718293a4b5c6d7e8f90",
+                75,
+            ),
+            (
+                "linux-meta-5.15.0.105.102-e4b3c1d2a5f60718293a4b5c6d7e8f90",
+                25,
+            ),
+            (
+                "firefox-1:1snap1-0ubuntu2-b0b5a7e1c2d34f5e6a7b8c9d0e1f2a3b",
+                71,
+            ),
+            ("--", 45),
+        ];
+
+        for (seed, expected) in cases {
+            assert_eq!(bucket(seed.as_bytes()), expected, "{}", seed);
+        }
+    }
+
+    #[test]
+    fn defers_above_percentage() {
+        let policy = policy();
+
+        // apt's bucket for this triple is 73
+        assert!(policy.defers("apt", "2.4.11", 50, false));
+        assert!(policy.defers("apt", "2.4.11", 72, false));
+        assert!(!policy.defers("apt", "2.4.11", 73, false));
+        assert!(!policy.defers("apt", "2.4.11", 50, true));
+        assert!(!policy.defers("apt", "2.4.11", 100, false));
+    }
+
+    #[test]
+    fn settings_and_exemptions() {
+        let always = Policy {
+            always_include: true,
+            never_include: true,
+            ..policy()
+        };
+        assert!(!always.defers("apt", "2.4.11", 0, false));
+
+        let never = Policy {
+            never_include: true,
+            ..policy()
+        };
+        assert!(never.defers("openssl", "3.0.2-0ubuntu1.15", 99, false));
+
+        let exempt = Policy {
+            exempt: true,
+            ..policy()
+        };
+        assert!(!exempt.defers("apt", "2.4.11", 0, false));
+
+        let no_machine_id = Policy {
+            machine_id: String::new(),
+            ..policy()
+        };
+        assert!(!no_machine_id.defers("apt", "2.4.11", 0, false));
+    }
+}
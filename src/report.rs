@@ -0,0 +1,324 @@
+use crate::{
+    apt::{OutputError, ProgressOutput, Upgradeable},
+    cve::Severity,
+    news::Entry,
+    repository::{self, Issue},
+    sources::Finding,
+    util,
+};
+use chrono::{DateTime, Utc};
+use rust_apt::util::{unit_str, NumSys};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+/// Version of the serialized report, see `schema/report.v1.schema.json`.
+///
+/// Bumped whenever a field is removed or changes meaning.
+pub const REPORT_VERSION: u32 = 1;
+
+const REBOOT_REQUIRED: &str = "/var/run/reboot-required";
+const REBOOT_REQUIRED_PKGS: &str = "/var/run/reboot-required.pkgs";
+
+/// Everything known about a single check, used by all renderers.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Report {
+    pub version: u32,
+    pub host: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub packages: Vec<Upgradeable>,
+    /// Held packages, listed separately
+    #[serde(default)]
+    pub held: Vec<Upgradeable>,
+    /// Number of packages removed by [`crate::filter::Filter`]
+    #[serde(default)]
+    pub suppressed: usize,
+    /// Results of fetching the package lists.
+    pub fetch: Vec<ProgressOutput>,
+    /// Errors and warnings reported by apt.
+    pub errors: Vec<OutputError>,
+    /// Sources that couldn't be updated, from `fetch` and `errors`
+    #[serde(default)]
+    pub repositories: Vec<Issue>,
+    /// Risky or deprecated sources, if `[sources] report` is set
+    #[serde(default)]
+    pub sources: Vec<Finding>,
+    pub reboot: Reboot,
+}
+
+impl Report {
+    pub fn new(packages: Vec<Upgradeable>, output: Vec<ProgressOutput>) -> Self {
+        let (errors, fetch): (Vec<ProgressOutput>, Vec<ProgressOutput>) = output
+            .into_iter()
+            .partition(|o| matches!(o, ProgressOutput::Error(_)));
+
+        let errors = errors
+            .into_iter()
+            .filter_map(|o| match o {
+                ProgressOutput::Error(err) => Some(err),
+                _ => None,
+            })
+            .collect::<Vec<OutputError>>();
+        let repositories = repository::issues(&fetch, &errors);
+
+        Self {
+            version: REPORT_VERSION,
+            host: util::host_label(),
+            timestamp: Utc::now(),
+            packages,
+            held: Vec::new(),
+            suppressed: 0,
+            fetch,
+            errors,
+            repositories,
+            sources: Vec::new(),
+            reboot: Reboot::read(),
+        }
+    }
+
+    /// Fetch results followed by apt errors, in the order apt prints them.
+    pub fn output(&self) -> Vec<String> {
+        self.fetch
+            .iter()
+            .map(|o| o.to_string())
+            .chain(self.errors.iter().map(|e| e.to_string()))
+            .collect()
+    }
+
+    /// Total download size of the unacknowledged packages in bytes.
+    pub fn download_size(&self) -> u64 {
+        self.unacknowledged().map(|pkg| pkg.download_size).sum()
+    }
+
+    /// Total change in installed size of the unacknowledged packages in bytes.
+    pub fn installed_size_delta(&self) -> i64 {
+        self.unacknowledged().map(|pkg| pkg.installed_size_delta).sum()
+    }
+
+    /// e.g. "23 packages, 148.00 MB to download, +12.00 MB disk", for the unacknowledged
+    /// packages, as counted by the subject.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} package(s), {} to download, {} disk",
+            self.unacknowledged().count(),
+            unit_str(self.download_size(), NumSys::Decimal),
+            delta_str(self.installed_size_delta()),
+        )
+    }
+
+    /// e.g. "3 high, 1 medium CVE(s) fixed" by the unacknowledged packages, counting each
+    /// CVE once.
+    pub fn cve_summary(&self) -> Option<String> {
+        cve_summary(self.unacknowledged())
+    }
+
+    /// `NEWS.Debian` entries of the listed packages, once per source package and version.
+    pub fn news(&self) -> Vec<&Entry> {
+        let mut seen = BTreeSet::new();
+
+        self.packages
+            .iter()
+            .flat_map(|pkg| pkg.news.iter())
+            .filter(|entry| seen.insert((&entry.source, &entry.version)))
+            .collect()
+    }
+
+    /// Packages that have not been acknowledged.
+    pub fn unacknowledged(&self) -> impl Iterator<Item = &Upgradeable> {
+        self.packages.iter().filter(|pkg| pkg.acknowledged.is_none())
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Formats a size difference in bytes with its sign, e.g. `+1.20 MB`.
+pub fn delta_str(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{}{}", sign, unit_str(delta.unsigned_abs(), NumSys::Decimal))
+}
+
+/// Reports for several targets sent as one notification, see [`crate::config::Config::targets`].
+#[derive(Deserialize, Serialize)]
+pub struct Combined {
+    pub version: u32,
+    pub timestamp: DateTime<Utc>,
+    pub targets: Vec<Report>,
+}
+
+impl Combined {
+    pub fn new(targets: Vec<Report>) -> Self {
+        Self {
+            version: REPORT_VERSION,
+            timestamp: Utc::now(),
+            targets,
+        }
+    }
+
+    /// Packages that have not been acknowledged, on all targets.
+    pub fn unacknowledged(&self) -> impl Iterator<Item = &Upgradeable> {
+        self.targets.iter().flat_map(|report| report.unacknowledged())
+    }
+
+    /// See [`Report::cve_summary`].
+    pub fn cve_summary(&self) -> Option<String> {
+        cve_summary(self.unacknowledged())
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn cve_summary<'a>(packages: impl Iterator<Item = &'a Upgradeable>) -> Option<String> {
+    let mut cves: BTreeMap<&str, Severity> = BTreeMap::new();
+    for cve in packages.flat_map(|pkg| pkg.cves.iter()) {
+        cves.insert(&cve.id, cve.severity);
+    }
+
+    if cves.is_empty() {
+        return None;
+    }
+
+    let mut counts: BTreeMap<Severity, usize> = BTreeMap::new();
+    for severity in cves.into_values() {
+        *counts.entry(severity).or_default() += 1;
+    }
+
+    let counts: Vec<String> = counts
+        .iter()
+        .rev()
+        .map(|(severity, count)| format!("{} {}", count, severity))
+        .collect();
+
+    Some(format!("{} CVE(s) fixed", counts.join(", ")))
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Reboot {
+    pub required: bool,
+    /// Packages that requested the reboot.
+    pub packages: Vec<String>,
+}
+
+impl Reboot {
+    pub fn read() -> Self {
+        let mut packages: Vec<String> = fs::read_to_string(util::in_root(REBOOT_REQUIRED_PKGS))
+            .map(|pkgs| pkgs.lines().map(String::from).collect())
+            .unwrap_or_default();
+        packages.sort();
+        packages.dedup();
+
+        Self {
+            required: util::in_root(REBOOT_REQUIRED).exists(),
+            packages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ack::Acknowledgement;
+
+    fn upgradeable(name: &str, download_size: u64, installed_size_delta: i64) -> Upgradeable {
+        Upgradeable {
+            name: name.to_string(),
+            download_size,
+            installed_size_delta,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary_leaves_out_acknowledged() {
+        let mut acknowledged = upgradeable("postgresql-15", 20_000_000, 1_000_000);
+        acknowledged.acknowledged = Some(Acknowledgement {
+            package: String::from("postgresql-15"),
+            version: String::new(),
+            until: None,
+            reason: None,
+            created: Utc::now(),
+        });
+
+        let report = Report {
+            packages: vec![
+                upgradeable("bash", 1_500_000, 0),
+                upgradeable("libssl3", 2_000_000, -500_000),
+                acknowledged,
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(report.download_size(), 3_500_000);
+        assert_eq!(report.installed_size_delta(), -500_000);
+        assert_eq!(
+            report.summary(),
+            format!(
+                "2 package(s), {} to download, -{} disk",
+                unit_str(3_500_000, NumSys::Decimal),
+                unit_str(500_000, NumSys::Decimal)
+            )
+        );
+    }
+
+    #[test]
+    fn minimal_v1_report() {
+        // Only the properties schema/report.v1.schema.json requires
+        let json = r#"{
+            "version": 1,
+            "host": "web1",
+            "timestamp": "2024-05-01T06:00:00Z",
+            "packages": [
+                {
+                    "name": "libssl3",
+                    "installed": "3.0.11-1~deb12u2",
+                    "candidate": "3.0.13-1~deb12u1",
+                    "archive": "bookworm-security",
+                    "arch": "amd64"
+                }
+            ],
+            "fetch": [],
+            "errors": [],
+            "reboot": { "required": false, "packages": [] }
+        }"#;
+
+        let report: Report = serde_json::from_str(json).unwrap();
+        assert_eq!(report.host.as_deref(), Some("web1"));
+        assert_eq!(report.packages[0].name, "libssl3");
+        assert!(report.packages[0].is_security());
+        assert!(report.held.is_empty());
+
+        let report: Report = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(report.packages[0].candidate.as_deref(), Some("3.0.13-1~deb12u1"));
+    }
+
+    #[test]
+    fn error_messages_from_agents() {
+        let json = r#"[
+            { "level": "error", "message": "" },
+            { "level": "warning", "message": "é" },
+            { "level": "error", "message": "E:Unable to locate package foo" },
+            { "level": "notice", "message": "Repository changed its Suite value" }
+        ]"#;
+
+        let errors: Vec<OutputError> = serde_json::from_str(json).unwrap();
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "E: ",
+                "W: é",
+                "E: Unable to locate package foo",
+                "N: Repository changed its Suite value"
+            ]
+        );
+
+        let err = OutputError::try_from("W: Some index files failed to download").unwrap();
+        assert_eq!(err.message(), "Some index files failed to download");
+        assert!(OutputError::try_from("Unknown").is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::apt::{ItemState, OutputError, ProgressOutput};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What went wrong with a source while updating the package lists.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// Authentication failed (`401`, `403`, or a file that couldn't be verified)
+    Auth,
+    /// Transient network error, e.g. DNS or a timeout
+    Network,
+    /// `404 Not Found`, or no Release file
+    NotFound,
+    /// The Release file is past its `Valid-Until`
+    Expired,
+    /// The Release file is signed with a key that isn't trusted (`NO_PUBKEY`)
+    MissingKey,
+    Other,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auth => write!(f, "authentication failed"),
+            Self::Network => write!(f, "network error"),
+            Self::NotFound => write!(f, "not found"),
+            Self::Expired => write!(f, "Release file expired"),
+            Self::MissingKey => write!(f, "missing key"),
+            Self::Other => write!(f, "failed"),
+        }
+    }
+}
+
+/// A problem with a source, e.g. `http://deb.debian.org/debian bookworm`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Issue {
+    pub source: String,
+    pub problem: Problem,
+    /// What apt said about it
+    pub message: String,
+}
+
+/// Settings for repository problems.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Repositories {
+    /// Send a notification about repository problems even when there are no updates
+    pub notify_without_updates: bool,
+}
+
+/// Sorts the failed fetches and apt errors into one issue per source and problem.
+pub fn issues(fetch: &[ProgressOutput], errors: &[OutputError]) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+
+    for output in fetch {
+        // `Ign:` lines are expected, e.g. for an `InRelease` falling back to `Release`
+        if let ProgressOutput::Fail {
+            description,
+            status:
+                status @ (ItemState::StatError
+                | ItemState::StatAuthError
+                | ItemState::StatTransientNetworkError),
+            error_text,
+            ..
+        } = output
+        {
+            let problem = match status {
+                ItemState::StatAuthError => Problem::Auth,
+                ItemState::StatTransientNetworkError => Problem::Network,
+                _ => classify(error_text),
+            };
+
+            push(&mut issues, fetch_source(description), problem, error_text);
+        }
+    }
+
+    let gpg = Regex::new(r"GPG error: (\S+) (\S+)[^:]*: .*NO_PUBKEY").unwrap();
+    let expired = Regex::new(r"Release file for (\S+) is expired").unwrap();
+    let failed = Regex::new(r"Failed to fetch (\S+)\s+(.*)").unwrap();
+    let no_release = Regex::new(
+        r"The repository '(\S+) (\S+)[^']*' (?:does not have|no longer has) a Release file",
+    )
+    .unwrap();
+
+    for err in errors {
+        let message = match err {
+            OutputError::Error(_) | OutputError::Warning(_) => err.message(),
+            _ => continue,
+        };
+
+        if let Some(captures) = gpg.captures(message) {
+            let source = format!("{} {}", &captures[1], &captures[2]);
+            push(&mut issues, source, Problem::MissingKey, message);
+        } else if let Some(captures) = expired.captures(message) {
+            push(
+                &mut issues,
+                url_source(&captures[1]),
+                Problem::Expired,
+                message,
+            );
+        } else if let Some(captures) = failed.captures(message) {
+            let problem = classify(&captures[2]);
+            push(&mut issues, url_source(&captures[1]), problem, message);
+        } else if let Some(captures) = no_release.captures(message) {
+            let source = format!("{} {}", &captures[1], &captures[2]);
+            push(&mut issues, source, Problem::NotFound, message);
+        }
+    }
+
+    issues
+}
+
+/// Adds the issue, unless the source already has the same problem (apt reports a failed
+/// fetch both as `Err:` and as `W: Failed to fetch`).
+fn push(issues: &mut Vec<Issue>, source: String, problem: Problem, message: &str) {
+    if issues
+        .iter()
+        .any(|issue| issue.source == source && issue.problem == problem)
+    {
+        return;
+    }
+
+    issues.push(Issue {
+        source,
+        problem,
+        message: message.to_string(),
+    });
+}
+
+fn classify(error_text: &str) -> Problem {
+    // HTTP errors start with the status, e.g. `404  Not Found [IP: 151.101.2.132 80]`; other
+    // numbers, e.g. in `Could not connect to mirror.example.com:4040`, aren't statuses
+    let status = error_text
+        .split_whitespace()
+        .next()
+        .filter(|word| word.len() == 3 && word.bytes().all(|b| b.is_ascii_digit()));
+
+    match status {
+        Some("404" | "410") => return Problem::NotFound,
+        Some("401" | "403") => return Problem::Auth,
+        Some(_) => return Problem::Other,
+        None => {}
+    }
+
+    let text = error_text.to_lowercase();
+
+    if [
+        "temporary failure",
+        "could not resolve",
+        "could not connect",
+        "timed out",
+        "connection failed",
+    ]
+    .iter()
+    .any(|s| text.contains(s))
+    {
+        Problem::Network
+    } else {
+        Problem::Other
+    }
+}
+
+/// `http://deb.debian.org/debian bookworm` from a fetch description such as
+/// `http://deb.debian.org/debian bookworm InRelease`.
+fn fetch_source(description: &str) -> String {
+    let mut words = description.split_whitespace();
+
+    match (words.next(), words.next()) {
+        // `bookworm/main` for the index files of a component
+        (Some(uri), Some(suite)) => format!("{} {}", uri, suite.split('/').next().unwrap_or(suite)),
+        _ => description.to_string(),
+    }
+}
+
+/// `http://deb.debian.org/debian bookworm` from a URL such as
+/// `http://deb.debian.org/debian/dists/bookworm/InRelease`.
+fn url_source(url: &str) -> String {
+    match url.split_once("/dists/") {
+        Some((uri, path)) => format!("{} {}", uri, path.split('/').next().unwrap_or_default()),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail(description: &str, status: ItemState, error_text: &str) -> ProgressOutput {
+        ProgressOutput::Fail {
+            id: 1,
+            description: description.to_string(),
+            status,
+            error_text: error_text.to_string(),
+        }
+    }
+
+    fn problems(issues: &[Issue]) -> Vec<(&str, Problem)> {
+        issues
+            .iter()
+            .map(|issue| (issue.source.as_str(), issue.problem))
+            .collect()
+    }
+
+    #[test]
+    fn missing_key() {
+        let errors = [
+            OutputError::Warning(String::from(
+                "GPG error: https://packages.example.com/debian bookworm InRelease: The following \
+                 signatures couldn't be verified because the public key is not available: \
+                 NO_PUBKEY 6ED0E7B82643E131",
+            )),
+            OutputError::Error(String::from(
+                "The repository 'https://packages.example.com/debian bookworm InRelease' is not signed.",
+            )),
+        ];
+
+        assert_eq!(
+            problems(&issues(&[], &errors)),
+            vec![(
+                "https://packages.example.com/debian bookworm",
+                Problem::MissingKey
+            )]
+        );
+    }
+
+    #[test]
+    fn expired_release() {
+        let errors = [OutputError::Error(String::from(
+            "Release file for \
+             http://snapshot.debian.org/archive/debian/20230101T000000Z/dists/bookworm/InRelease \
+             is expired (invalid since 300d 2h 5min 12s). Updates for this repository will not be \
+             applied.",
+        ))];
+
+        assert_eq!(
+            problems(&issues(&[], &errors)),
+            vec![(
+                "http://snapshot.debian.org/archive/debian/20230101T000000Z bookworm",
+                Problem::Expired
+            )]
+        );
+    }
+
+    #[test]
+    fn not_found() {
+        let fetch = [
+            fail(
+                "http://deb.example.com/debian trixie InRelease",
+                ItemState::StatIdle,
+                "404  Not Found [IP: 203.0.113.7 80]",
+            ),
+            fail(
+                "http://deb.example.com/debian trixie Release",
+                ItemState::StatError,
+                "404  Not Found [IP: 203.0.113.7 80]",
+            ),
+        ];
+        let errors = [OutputError::Error(String::from(
+            "The repository 'http://deb.example.com/debian trixie Release' does not have a \
+             Release file.",
+        ))];
+
+        let issues = issues(&fetch, &errors);
+
+        assert_eq!(
+            problems(&issues),
+            vec![("http://deb.example.com/debian trixie", Problem::NotFound)]
+        );
+        assert_eq!(issues[0].message, "404  Not Found [IP: 203.0.113.7 80]");
+    }
+
+    #[test]
+    fn unauthorized() {
+        let fetch = [fail(
+            "https://apt.example.com/private stable InRelease",
+            ItemState::StatError,
+            "401  Unauthorized [IP: 198.51.100.4 443]",
+        )];
+        let errors = [OutputError::Error(String::from(
+            "Failed to fetch https://apt.example.com/private/dists/stable/InRelease  401  \
+             Unauthorized [IP: 198.51.100.4 443]",
+        ))];
+
+        assert_eq!(
+            problems(&issues(&fetch, &errors)),
+            vec![("https://apt.example.com/private stable", Problem::Auth)]
+        );
+    }
+
+    #[test]
+    fn transient_dns_failure() {
+        let fetch = [
+            fail(
+                "http://deb.debian.org/debian bookworm InRelease",
+                ItemState::StatTransientNetworkError,
+                "Temporary failure resolving 'deb.debian.org'",
+            ),
+            fail(
+                "http://deb.debian.org/debian bookworm-updates InRelease",
+                ItemState::StatTransientNetworkError,
+                "Temporary failure resolving 'deb.debian.org'",
+            ),
+        ];
+        let errors = [
+            OutputError::Warning(String::from(
+                "Failed to fetch http://deb.debian.org/debian/dists/bookworm/InRelease  \
+                 Temporary failure resolving 'deb.debian.org'",
+            )),
+            OutputError::Warning(String::from(
+                "Some index files failed to download. They have been ignored, or old ones used \
+                 instead.",
+            )),
+        ];
+
+        assert_eq!(
+            problems(&issues(&fetch, &errors)),
+            vec![
+                ("http://deb.debian.org/debian bookworm", Problem::Network),
+                (
+                    "http://deb.debian.org/debian bookworm-updates",
+                    Problem::Network
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn status_code_not_taken_from_the_address() {
+        assert_eq!(
+            classify("Connection failed [IP: 192.0.2.40 4040]"),
+            Problem::Network
+        );
+        assert_eq!(
+            classify("403  Forbidden [IP: 192.0.2.40 80]"),
+            Problem::Auth
+        );
+        assert_eq!(classify("500  Internal Server Error"), Problem::Other);
+        assert_eq!(
+            classify("Could not connect to mirror.example.com:4040 (192.0.2.40). - connect (111: Connection refused)"),
+            Problem::Network
+        );
+        assert_eq!(
+            classify("Could not resolve 'mirror403.example.com'"),
+            Problem::Network
+        );
+        assert_eq!(classify("Hash Sum mismatch 404"), Problem::Other);
+    }
+}
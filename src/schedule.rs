@@ -0,0 +1,280 @@
+use crate::error::{Error, Result};
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// When notifications are delivered. During quiet hours they are queued and sent once the
+/// window is over.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// IANA time zone of the windows, e.g. `Europe/Berlin` (default: the system's)
+    pub timezone: Option<String>,
+    /// Windows during which notifications are queued
+    pub quiet: Vec<Window>,
+    /// Notifications containing security updates are sent during quiet hours
+    pub security_bypass: bool,
+    /// Notifications containing apt errors are sent during quiet hours
+    pub errors_bypass: bool,
+}
+
+impl Default for Schedule {
+    fn default() -> Schedule {
+        Schedule {
+            timezone: None,
+            quiet: Vec::new(),
+            security_bypass: true,
+            errors_bypass: true,
+        }
+    }
+}
+
+/// A daily window, e.g. `22:00` to `07:00`. A window ending at or before its start runs
+/// past midnight, so `00:00` to `00:00` is the whole day.
+#[derive(Deserialize, Serialize)]
+pub struct Window {
+    /// `HH:MM`
+    pub start: String,
+    /// `HH:MM`
+    pub end: String,
+    /// Days the window starts on (default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+impl Window {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|err| Error::Schedule(format!("{}: {}", time, err)))
+        };
+
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    /// The end of the window, if `now` is within it.
+    fn end_after(&self, now: NaiveDateTime) -> Result<Option<NaiveDateTime>> {
+        let (start, end) = self.times()?;
+        let (date, time) = (now.date(), now.time());
+
+        let end = if start < end {
+            (self.starts_on(date.weekday()) && start <= time && time < end)
+                .then(|| date.and_time(end))
+        } else if time >= start && self.starts_on(date.weekday()) {
+            Some((date + Duration::days(1)).and_time(end))
+        } else if time < end && self.starts_on(date.weekday().pred()) {
+            Some(date.and_time(end))
+        } else {
+            None
+        };
+
+        Ok(end)
+    }
+}
+
+impl Schedule {
+    /// Whether a notification is sent during quiet hours.
+    pub fn bypasses(&self, security: bool, errors: bool) -> bool {
+        (security && self.security_bypass) || (errors && self.errors_bypass)
+    }
+
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> Result<bool> {
+        Ok(self.quiet_until(now)?.is_some())
+    }
+
+    /// The end of the quiet hours, if `now` is within them. Adjoining windows are merged.
+    pub fn quiet_until(&self, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>> {
+        let tz = self.tz()?;
+        let mut until: Option<NaiveDateTime> = None;
+        let mut at = self.local(tz, now);
+
+        // Bounded, as windows covering every day would never end
+        for _ in 0..=7 * self.quiet.len() {
+            let mut end = None;
+            for window in &self.quiet {
+                end = end.max(window.end_after(at)?);
+            }
+
+            match end {
+                Some(end) => {
+                    until = Some(end);
+                    at = end;
+                }
+                None => break,
+            }
+        }
+
+        until.map(|until| self.utc(tz, until)).transpose()
+    }
+
+    fn tz(&self) -> Result<Option<Tz>> {
+        self.timezone
+            .as_deref()
+            .map(|name| {
+                name.parse::<Tz>()
+                    .map_err(|err| Error::Schedule(format!("{}: {}", name, err)))
+            })
+            .transpose()
+    }
+
+    fn local(&self, tz: Option<Tz>, time: DateTime<Utc>) -> NaiveDateTime {
+        match tz {
+            Some(tz) => time.with_timezone(&tz).naive_local(),
+            None => time.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    fn utc(&self, tz: Option<Tz>, time: NaiveDateTime) -> Result<DateTime<Utc>> {
+        let utc = match tz {
+            Some(tz) => tz
+                .from_local_datetime(&time)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+            None => Local
+                .from_local_datetime(&time)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc)),
+        };
+
+        // Skipped by a daylight saving time change
+        utc.map_or_else(|| self.utc(tz, time + Duration::hours(1)), Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn window(start: &str, end: &str, days: &[Weekday]) -> Window {
+        Window {
+            start: start.to_string(),
+            end: end.to_string(),
+            days: days.to_vec(),
+        }
+    }
+
+    fn local(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn berlin(quiet: Vec<Window>) -> Schedule {
+        Schedule {
+            timezone: Some(String::from("Europe/Berlin")),
+            quiet,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn end_after_within_a_day() {
+        let window = window("12:00", "14:00", &[]);
+
+        assert_eq!(
+            window.end_after(local("2024-05-06", "12:00")).unwrap(),
+            Some(local("2024-05-06", "14:00"))
+        );
+        assert_eq!(
+            window.end_after(local("2024-05-06", "14:00")).unwrap(),
+            None
+        );
+        assert_eq!(
+            window.end_after(local("2024-05-06", "11:59")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn end_after_across_midnight() {
+        // 2024-05-06 is a Monday
+        let window = window("22:00", "07:00", &[Weekday::Mon]);
+
+        assert_eq!(
+            window.end_after(local("2024-05-06", "23:00")).unwrap(),
+            Some(local("2024-05-07", "07:00"))
+        );
+        assert_eq!(
+            window.end_after(local("2024-05-07", "03:00")).unwrap(),
+            Some(local("2024-05-07", "07:00"))
+        );
+        // Started on Sunday
+        assert_eq!(
+            window.end_after(local("2024-05-06", "03:00")).unwrap(),
+            None
+        );
+        assert_eq!(
+            window.end_after(local("2024-05-07", "23:00")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_time() {
+        assert!(window("25:00", "07:00", &[])
+            .end_after(local("2024-05-06", "23:00"))
+            .is_err());
+    }
+
+    #[test]
+    fn adjoining_windows_are_merged() {
+        // The weekend, from Friday night to Monday morning
+        let schedule = berlin(vec![
+            window("22:00", "00:00", &[Weekday::Fri]),
+            window("00:00", "00:00", &[Weekday::Sat, Weekday::Sun]),
+            window("00:00", "07:00", &[Weekday::Mon]),
+        ]);
+
+        // Saturday 2024-05-11 12:00 CEST
+        assert_eq!(
+            schedule.quiet_until(utc("2024-05-11T10:00:00Z")).unwrap(),
+            Some(utc("2024-05-13T05:00:00Z"))
+        );
+        assert!(!schedule.is_quiet(utc("2024-05-13T05:00:00Z")).unwrap());
+    }
+
+    #[test]
+    fn end_skipped_by_dst() {
+        // 02:00 to 03:00 doesn't exist on 2024-03-31 in Berlin
+        let schedule = berlin(vec![window("01:00", "02:30", &[])]);
+
+        assert_eq!(
+            schedule.quiet_until(utc("2024-03-31T00:30:00Z")).unwrap(),
+            Some(utc("2024-03-31T01:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn end_repeated_by_dst() {
+        // 02:00 to 03:00 happens twice on 2024-10-27 in Berlin; the window ends the first time
+        let schedule = berlin(vec![window("22:00", "02:30", &[])]);
+
+        assert_eq!(
+            schedule.quiet_until(utc("2024-10-26T22:00:00Z")).unwrap(),
+            Some(utc("2024-10-27T00:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn bypass() {
+        let schedule = Schedule {
+            errors_bypass: false,
+            ..Default::default()
+        };
+
+        assert!(schedule.bypasses(true, false));
+        assert!(!schedule.bypasses(false, true));
+        assert!(!schedule.bypasses(false, false));
+    }
+}
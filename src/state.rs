@@ -0,0 +1,114 @@
+use crate::{apt::Upgradeable, error::Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+const PENDING_FILE: &str = "pending.json";
+
+/// When each pending update was first seen, keyed by package name and candidate version.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Pending(BTreeMap<String, BTreeMap<String, DateTime<Utc>>>);
+
+impl Pending {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let file = dir.join(PENDING_FILE);
+
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(PENDING_FILE), serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// Records the packages as pending and sets their `pending_since`.
+    ///
+    /// Updates that are no longer pending (installed, or replaced by a newer candidate) are forgotten.
+    pub fn track(&mut self, packages: &mut [Upgradeable], now: DateTime<Utc>) {
+        let mut pending: BTreeMap<String, BTreeMap<String, DateTime<Utc>>> = BTreeMap::new();
+
+        for pkg in packages.iter_mut() {
+            let candidate = pkg.candidate.clone().unwrap_or_default();
+
+            let since = self
+                .0
+                .get(&pkg.name)
+                .and_then(|versions| versions.get(&candidate))
+                .copied()
+                .unwrap_or(now);
+
+            pending
+                .entry(pkg.name.clone())
+                .or_default()
+                .insert(candidate, since);
+
+            pkg.pending_since = Some(since);
+        }
+
+        self.0 = pending;
+    }
+}
+
+/// Loads the pending state from `dir` and updates it with `packages`. It is saved unless
+/// `read_only`, e.g. for `--output json`, which shouldn't need write access to `dir`.
+pub fn track_pending(dir: &Path, packages: &mut [Upgradeable], read_only: bool) -> Result<()> {
+    let mut pending = Pending::load(dir)?;
+    pending.track(packages, Utc::now());
+
+    match read_only {
+        true => Ok(()),
+        false => pending.save(dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn upgradeable(name: &str, candidate: &str) -> Upgradeable {
+        Upgradeable {
+            name: name.to_string(),
+            candidate: Some(candidate.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn track_keeps_first_seen() {
+        let first = Utc.with_ymd_and_hms(2024, 5, 1, 6, 0, 0).unwrap();
+        let later = first + Duration::days(3);
+        let mut pending = Pending::default();
+
+        pending.track(&mut [upgradeable("bash", "5.2.15-2+b2")], first);
+
+        let mut packages = [upgradeable("bash", "5.2.15-2+b2"), upgradeable("curl", "7.88.1-10")];
+        pending.track(&mut packages, later);
+
+        assert_eq!(packages[0].pending_since, Some(first));
+        assert_eq!(packages[0].pending_days(later), Some(3));
+        assert_eq!(packages[1].pending_since, Some(later));
+    }
+
+    #[test]
+    fn track_forgets_replaced_candidates() {
+        let first = Utc.with_ymd_and_hms(2024, 5, 1, 6, 0, 0).unwrap();
+        let later = first + Duration::days(3);
+        let mut pending = Pending::default();
+
+        pending.track(&mut [upgradeable("bash", "5.2.15-2+b2")], first);
+
+        let mut packages = [upgradeable("bash", "5.2.15-2+b3")];
+        pending.track(&mut packages, later);
+        assert_eq!(packages[0].pending_since, Some(later));
+
+        pending.track(&mut [], later);
+        assert!(pending.0.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    mail,
+    report::Report,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    sync::{PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Read-only HTTP endpoint serving the latest report of the local host.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Status {
+    pub listen: String,
+    /// Minutes between refreshes of the report
+    pub refresh_minutes: u64,
+}
+
+impl Default for Status {
+    fn default() -> Status {
+        Status {
+            listen: String::from("127.0.0.1:9187"),
+            refresh_minutes: 60,
+        }
+    }
+}
+
+/// The latest report, or why it couldn't be built.
+#[derive(Default)]
+struct Latest {
+    report: Option<Report>,
+    error: Option<String>,
+    refreshed: Option<DateTime<Utc>>,
+}
+
+impl Status {
+    /// Serves `/status.json`, `/metrics` and `/` on `listen`, calling `refresh` every
+    /// `refresh_minutes` to rebuild the report.
+    pub fn serve<F>(&self, config: &Config, refresh: F) -> Result<()>
+    where
+        F: Fn() -> Result<Report> + Sync,
+    {
+        let server = Server::http(&self.listen)
+            .map_err(|err| Error::Http(format!("{}: {}", self.listen, err)))?;
+        let latest = RwLock::new(Latest::default());
+
+        thread::scope(|scope| {
+            scope.spawn(|| loop {
+                let result = refresh();
+                // Only whole fields are assigned, so a panic can't leave them inconsistent
+                let mut latest = latest.write().unwrap_or_else(PoisonError::into_inner);
+
+                match result {
+                    Ok(report) => {
+                        latest.report = Some(report);
+                        latest.error = None;
+                    }
+                    Err(err) => {
+                        eprintln!("Refreshing report: {}", err);
+                        latest.error = Some(err.to_string());
+                    }
+                }
+                latest.refreshed = Some(Utc::now());
+                drop(latest);
+
+                thread::sleep(Duration::from_secs(self.refresh_minutes * 60));
+            });
+
+            for request in server.incoming_requests() {
+                handle(
+                    config,
+                    &latest.read().unwrap_or_else(PoisonError::into_inner),
+                    request,
+                );
+            }
+        });
+
+        Ok(())
+    }
+}
+
+fn handle(config: &Config, latest: &Latest, request: Request) {
+    let response = match (request.method(), request.url(), &latest.report) {
+        (Method::Get, "/metrics", _) => with_type(
+            Response::from_string(metrics(latest)),
+            "text/plain; version=0.0.4",
+        ),
+        // Not refreshed yet
+        (Method::Get, "/status.json" | "/", None) => {
+            Response::from_string("").with_status_code(503)
+        }
+        (Method::Get, "/status.json", Some(report)) => match report.to_json() {
+            Ok(json) => with_type(Response::from_string(json), "application/json"),
+            Err(err) => Response::from_string(err.to_string()).with_status_code(500),
+        },
+        (Method::Get, "/", Some(report)) => with_type(
+            Response::from_string(mail::make_html(config, report)),
+            "text/html; charset=utf-8",
+        ),
+        _ => Response::from_string("").with_status_code(404),
+    };
+
+    if let Err(err) = request.respond(response) {
+        eprintln!("Responding: {}", err);
+    }
+}
+
+fn with_type<R: std::io::Read>(response: Response<R>, content_type: &str) -> Response<R> {
+    match Header::from_bytes("Content-Type", content_type) {
+        Ok(header) => response.with_header(header),
+        Err(()) => response,
+    }
+}
+
+/// Prometheus text exposition format.
+fn metrics(latest: &Latest) -> String {
+    let mut buf = String::new();
+    let mut gauge = |name: &str, help: &str, value: f64| {
+        let _ = writeln!(buf, "# HELP {} {}", name, help);
+        let _ = writeln!(buf, "# TYPE {} gauge", name);
+        let _ = writeln!(buf, "{} {}", name, value);
+    };
+
+    gauge(
+        "apt_updates_refresh_success",
+        "Whether the last refresh of the report succeeded.",
+        if latest.error.is_none() && latest.report.is_some() {
+            1.0
+        } else {
+            0.0
+        },
+    );
+
+    if let Some(refreshed) = latest.refreshed {
+        gauge(
+            "apt_updates_last_refresh_timestamp_seconds",
+            "When the report was last refreshed.",
+            refreshed.timestamp() as f64,
+        );
+    }
+
+    let Some(report) = &latest.report else {
+        return buf;
+    };
+
+    let unacknowledged: Vec<_> = report.unacknowledged().collect();
+
+    gauge(
+        "apt_updates_pending",
+        "Upgradeable packages that have not been acknowledged.",
+        unacknowledged.len() as f64,
+    );
+    gauge(
+        "apt_updates_security_pending",
+        "Upgradeable packages from a security archive that have not been acknowledged.",
+        unacknowledged
+            .iter()
+            .filter(|pkg| pkg.is_security())
+            .count() as f64,
+    );
+    gauge(
+        "apt_updates_acknowledged",
+        "Upgradeable packages that have been acknowledged.",
+        (report.packages.len() - unacknowledged.len()) as f64,
+    );
+    gauge(
+        "apt_updates_held",
+        "Upgradeable packages held back deliberately and listed separately.",
+        report.held.len() as f64,
+    );
+    gauge(
+        "apt_updates_oldest_pending_seconds",
+        "How long the oldest unacknowledged update has been pending.",
+        unacknowledged
+            .iter()
+            .filter_map(|pkg| pkg.pending_since)
+            .map(|since| (report.timestamp - since).num_seconds())
+            .max()
+            .unwrap_or(0) as f64,
+    );
+    gauge(
+        "apt_updates_download_bytes",
+        "Total download size of the unacknowledged upgradeable packages.",
+        report.download_size() as f64,
+    );
+    gauge(
+        "apt_updates_reboot_required",
+        "Whether a system restart is required.",
+        if report.reboot.required { 1.0 } else { 0.0 },
+    );
+    gauge(
+        "apt_updates_apt_errors",
+        "Errors and warnings from apt while updating the package lists.",
+        report.errors.len() as f64,
+    );
+    gauge(
+        "apt_updates_repository_problems",
+        "Sources that couldn't be updated.",
+        report.repositories.len() as f64,
+    );
+
+    buf
+}
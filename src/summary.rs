@@ -0,0 +1,438 @@
+#[cfg(feature = "mail")]
+use crate::config;
+use crate::{error::Result, report::Report};
+use chrono::{DateTime, Datelike, Duration, Local, Months, NaiveDate, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+const RUNS_FILE: &str = "summary.jsonl";
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cadence {
+    Weekly,
+    Monthly,
+}
+
+/// Collects the report of each run and sends a summary of them on a cadence, instead of a
+/// notification per run.
+#[derive(Deserialize, Serialize)]
+pub struct Summary {
+    pub cadence: Cadence,
+    /// Day weekly summaries are sent on
+    #[serde(default = "default_weekday")]
+    pub weekday: Weekday,
+    /// Day of the month monthly summaries are sent on, at most 28
+    #[serde(default = "default_day")]
+    pub day: u32,
+    #[serde(default = "default_subject")]
+    subject: String,
+}
+
+fn default_weekday() -> Weekday {
+    Weekday::Mon
+}
+
+fn default_day() -> u32 {
+    1
+}
+
+fn default_subject() -> String {
+    String::from("Summary: # update(s) pending")
+}
+
+/// An update as listed by one run.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub installed: Option<String>,
+    pub candidate: Option<String>,
+    pub security: bool,
+    pub acknowledged: bool,
+    pub pending_since: Option<DateTime<Utc>>,
+}
+
+/// What is kept of the report of a run.
+#[derive(Deserialize, Serialize)]
+pub struct Run {
+    pub timestamp: DateTime<Utc>,
+    pub packages: Vec<Package>,
+    /// Errors and warnings from apt while updating the package lists
+    pub errors: usize,
+}
+
+impl From<&Report> for Run {
+    fn from(report: &Report) -> Self {
+        Self {
+            timestamp: report.timestamp,
+            packages: report
+                .packages
+                .iter()
+                .map(|pkg| Package {
+                    name: pkg.name.clone(),
+                    installed: pkg.installed.clone(),
+                    candidate: pkg.candidate.clone(),
+                    security: pkg.is_security(),
+                    acknowledged: pkg.acknowledged.is_some(),
+                    pending_since: pkg.pending_since,
+                })
+                .collect(),
+            errors: report.errors.len(),
+        }
+    }
+}
+
+/// An update that was pending during the period and no longer is.
+pub struct Installed {
+    pub package: Package,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The runs since the last summary.
+pub struct Period {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub runs: usize,
+    /// Runs during which apt reported errors
+    pub error_runs: usize,
+    /// Updates listed by a run but not by the latest one, sorted by name
+    pub installed: Vec<Installed>,
+    /// Unacknowledged updates listed by the latest run
+    pub pending: Vec<Package>,
+}
+
+impl Period {
+    fn new(runs: &[Run]) -> Option<Self> {
+        let (first, last) = (runs.first()?, runs.last()?);
+
+        let mut installed: BTreeMap<&str, Installed> = BTreeMap::new();
+        for run in runs {
+            for pkg in &run.packages {
+                if last.packages.iter().any(|p| p.name == pkg.name) {
+                    continue;
+                }
+
+                installed
+                    .entry(&pkg.name)
+                    .and_modify(|seen| {
+                        seen.package = pkg.clone();
+                        seen.last_seen = run.timestamp;
+                    })
+                    .or_insert(Installed {
+                        package: pkg.clone(),
+                        first_seen: run.timestamp,
+                        last_seen: run.timestamp,
+                    });
+            }
+        }
+
+        Some(Self {
+            start: first.timestamp,
+            end: last.timestamp,
+            runs: runs.len(),
+            error_runs: runs.iter().filter(|run| run.errors > 0).count(),
+            installed: installed.into_values().collect(),
+            pending: last
+                .packages
+                .iter()
+                .filter(|pkg| !pkg.acknowledged)
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Whether there's nothing to report.
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty() && self.pending.is_empty() && self.error_runs == 0
+    }
+}
+
+impl Summary {
+    #[cfg(feature = "mail")]
+    pub fn subject_fmt(&self, count: usize) -> String {
+        config::format_subject(&self.subject, count)
+    }
+
+    /// Appends the report to the runs kept in `dir`.
+    pub fn record(&self, dir: &Path, report: &Report) -> Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(RUNS_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(&Run::from(report))?)?;
+
+        Ok(())
+    }
+
+    /// The period to summarize, if a summary is due: the runs kept in `dir` began before
+    /// the latest summary day.
+    pub fn due(&self, dir: &Path, now: DateTime<Utc>) -> Result<Option<Period>> {
+        let runs = match fs::read_to_string(dir.join(RUNS_FILE)) {
+            Ok(runs) => runs
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(serde_json::from_str)
+                .collect::<serde_json::Result<Vec<Run>>>()?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let Some(start) = runs.first().map(|run| run.timestamp) else {
+            return Ok(None);
+        };
+
+        if start >= self.latest_day(now) {
+            return Ok(None);
+        }
+
+        Ok(Period::new(&runs))
+    }
+
+    /// Forgets the runs kept in `dir` once they've been summarized, except the latest one:
+    /// it starts the next period, so that updates it lists and that are installed before
+    /// the next run are summarized as installed.
+    pub fn clear(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(RUNS_FILE);
+
+        let runs = match fs::read_to_string(&path) {
+            Ok(runs) => runs,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        match runs.lines().rfind(|line| !line.is_empty()) {
+            Some(last) => fs::write(path, format!("{}\n", last))?,
+            None => fs::remove_file(path)?,
+        }
+
+        Ok(())
+    }
+
+    /// Midnight (local time) of the latest day a summary is sent on, at or before `now`.
+    fn latest_day(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.with_timezone(&Local).date_naive();
+
+        let day = match self.cadence {
+            Cadence::Weekly => {
+                let days_back = (today.weekday().num_days_from_monday() + 7
+                    - self.weekday.num_days_from_monday())
+                    % 7;
+                today - Duration::days(days_back as i64)
+            }
+            Cadence::Monthly => {
+                let day = self.day.clamp(1, 28);
+                let this_month =
+                    NaiveDate::from_ymd_opt(today.year(), today.month(), day).unwrap_or(today);
+
+                if today.day() >= day {
+                    this_month
+                } else {
+                    this_month - Months::new(1)
+                }
+            }
+        };
+
+        Local
+            .from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .earliest()
+            .map_or(now, |midnight| midnight.with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, security: bool, acknowledged: bool) -> Package {
+        Package {
+            name: name.to_string(),
+            installed: Some(String::from("1")),
+            candidate: Some(String::from("2")),
+            security,
+            acknowledged,
+            pending_since: None,
+        }
+    }
+
+    fn run(day: u32, packages: Vec<Package>, errors: usize) -> Run {
+        Run {
+            timestamp: Utc.with_ymd_and_hms(2024, 5, day, 6, 0, 0).unwrap(),
+            packages,
+            errors,
+        }
+    }
+
+    fn summary(cadence: Cadence) -> Summary {
+        Summary {
+            cadence,
+            weekday: Weekday::Wed,
+            day: 15,
+            subject: default_subject(),
+        }
+    }
+
+    /// Noon, local time.
+    fn noon(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, 12, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn local_date(time: DateTime<Utc>) -> String {
+        time.with_timezone(&Local).format("%F %T").to_string()
+    }
+
+    #[test]
+    fn period_installed_and_pending() {
+        let runs = [
+            run(
+                1,
+                vec![package("bash", false, false), package("curl", true, false)],
+                0,
+            ),
+            run(
+                2,
+                vec![package("bash", false, false), package("vim", false, true)],
+                2,
+            ),
+            run(
+                3,
+                vec![
+                    package("bash", false, false),
+                    package("zlib1g", true, false),
+                ],
+                0,
+            ),
+        ];
+
+        let period = Period::new(&runs).unwrap();
+
+        assert_eq!(
+            (period.start, period.end),
+            (runs[0].timestamp, runs[2].timestamp)
+        );
+        assert_eq!((period.runs, period.error_runs), (3, 1));
+
+        let installed: Vec<(&str, DateTime<Utc>)> = period
+            .installed
+            .iter()
+            .map(|seen| (seen.package.name.as_str(), seen.first_seen))
+            .collect();
+        assert_eq!(
+            installed,
+            vec![("curl", runs[0].timestamp), ("vim", runs[1].timestamp)]
+        );
+
+        let pending: Vec<&str> = period.pending.iter().map(|pkg| pkg.name.as_str()).collect();
+        assert_eq!(pending, vec!["bash", "zlib1g"]);
+    }
+
+    #[test]
+    fn period_without_anything_to_report() {
+        let period = Period::new(&[run(1, vec![package("vim", false, true)], 0)]).unwrap();
+        assert!(period.is_empty());
+
+        let period = Period::new(&[run(1, Vec::new(), 1)]).unwrap();
+        assert!(!period.is_empty());
+
+        assert!(Period::new(&[]).is_none());
+    }
+
+    #[test]
+    fn latest_run_starts_the_next_period() {
+        let dir = std::env::temp_dir().join(format!(
+            "apt-updates-notifier-summary-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let append = |timestamp: DateTime<Utc>, packages: Vec<Package>| {
+            let run = Run {
+                timestamp,
+                packages,
+                errors: 0,
+            };
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(RUNS_FILE))
+                .unwrap();
+            writeln!(file, "{}", serde_json::to_string(&run).unwrap()).unwrap();
+        };
+        let weekly = summary(Cadence::Weekly);
+
+        // Tuesday, then Wednesday, the summary day
+        append(noon(2024, 5, 14), vec![package("curl", true, false)]);
+        append(noon(2024, 5, 15), vec![package("curl", true, false)]);
+
+        let period = weekly.due(&dir, noon(2024, 5, 15)).unwrap().unwrap();
+        assert_eq!(period.pending.len(), 1);
+        weekly.clear(&dir).unwrap();
+        assert!(weekly.due(&dir, noon(2024, 5, 16)).unwrap().is_none());
+
+        append(noon(2024, 5, 16), Vec::new());
+
+        let period = weekly.due(&dir, noon(2024, 5, 22)).unwrap().unwrap();
+        assert_eq!(period.start, noon(2024, 5, 15));
+        let installed: Vec<&str> = period
+            .installed
+            .iter()
+            .map(|seen| seen.package.name.as_str())
+            .collect();
+        assert_eq!(installed, vec!["curl"]);
+        assert!(period.pending.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn latest_weekly_day() {
+        let weekly = summary(Cadence::Weekly);
+
+        // Wednesday 2024-05-15
+        assert_eq!(
+            local_date(weekly.latest_day(noon(2024, 5, 15))),
+            "2024-05-15 00:00:00"
+        );
+        assert_eq!(
+            local_date(weekly.latest_day(noon(2024, 5, 14))),
+            "2024-05-08 00:00:00"
+        );
+        assert_eq!(
+            local_date(weekly.latest_day(noon(2024, 5, 20))),
+            "2024-05-15 00:00:00"
+        );
+    }
+
+    #[test]
+    fn latest_monthly_day() {
+        let monthly = summary(Cadence::Monthly);
+
+        assert_eq!(
+            local_date(monthly.latest_day(noon(2024, 5, 15))),
+            "2024-05-15 00:00:00"
+        );
+        assert_eq!(
+            local_date(monthly.latest_day(noon(2024, 5, 14))),
+            "2024-04-15 00:00:00"
+        );
+        assert_eq!(
+            local_date(monthly.latest_day(noon(2024, 1, 2))),
+            "2023-12-15 00:00:00"
+        );
+
+        let last = Summary { day: 31, ..monthly };
+        assert_eq!(
+            local_date(last.latest_day(noon(2024, 2, 29))),
+            "2024-02-28 00:00:00"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const MACHINES_DIR: &str = "/var/lib/machines";
+const LXC_DIR: &str = "/var/lib/lxc";
+
+/// A system checked in a combined run, see [`crate::config::Config::targets`].
+///
+/// At most one of `root`, `machine` and `lxc` may be set; a target with none of them is the host.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Target {
+    /// Shown in the notification (default: the machine or container name, or the root's os-release name)
+    pub name: Option<String>,
+    /// Extracted filesystem, e.g. a chroot
+    pub root: Option<PathBuf>,
+    /// systemd-nspawn machine in `/var/lib/machines`
+    pub machine: Option<String>,
+    /// LXC container, using `/var/lib/lxc/<name>/rootfs`
+    pub lxc: Option<String>,
+}
+
+impl Target {
+    /// The target's root directory, or `None` for the host.
+    pub fn root(&self) -> Result<Option<PathBuf>> {
+        let roots: Vec<PathBuf> = [
+            self.root.clone(),
+            self.machine
+                .as_ref()
+                .map(|machine| PathBuf::from(MACHINES_DIR).join(machine)),
+            self.lxc
+                .as_ref()
+                .map(|lxc| PathBuf::from(LXC_DIR).join(lxc).join("rootfs")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        match roots.len() {
+            0 | 1 => Ok(roots.into_iter().next()),
+            _ => Err(Error::Target(String::from(
+                "only one of root, machine and lxc may be set",
+            ))),
+        }
+    }
+
+    /// The configured name, or the machine or container name.
+    pub fn name(&self) -> Option<String> {
+        self.name
+            .clone()
+            .or(self.machine.clone())
+            .or(self.lxc.clone())
+    }
+}
@@ -0,0 +1,209 @@
+use crate::util;
+use rust_apt::config::Config;
+
+const UNATTENDED_UPGRADE: &str = "/usr/bin/unattended-upgrade";
+
+/// Release information of a package file, as used by unattended-upgrades to match origins.
+#[derive(Default)]
+pub struct Origin {
+    pub origin: String,
+    pub archive: String,
+    pub codename: String,
+    pub label: String,
+    pub component: String,
+    pub site: String,
+}
+
+/// The packages unattended-upgrades is configured to install, read from the apt configuration.
+pub struct Policy {
+    enabled: bool,
+    allowed_origins: Vec<String>,
+    origins_pattern: Vec<String>,
+    blacklist: Vec<regex::Regex>,
+}
+
+impl Policy {
+    pub fn from_apt_config() -> Self {
+        let config = Config::new();
+
+        let (distro_id, distro_codename) = distro();
+        let expand = |value: String| {
+            value
+                .replace("${distro_id}", &distro_id)
+                .replace("${distro_codename}", &distro_codename)
+        };
+
+        Self {
+            enabled: util::in_root(UNATTENDED_UPGRADE).exists()
+                && config.int("APT::Periodic::Unattended-Upgrade", 0) != 0,
+            allowed_origins: config
+                .find_vector("Unattended-Upgrade::Allowed-Origins")
+                .into_iter()
+                .map(expand)
+                .collect(),
+            origins_pattern: config
+                .find_vector("Unattended-Upgrade::Origins-Pattern")
+                .into_iter()
+                .map(expand)
+                .collect(),
+            // Python's re.match() only anchors at the start
+            blacklist: config
+                .find_vector("Unattended-Upgrade::Package-Blacklist")
+                .iter()
+                .filter_map(|pattern| regex::Regex::new(&format!("^(?:{})", pattern)).ok())
+                .collect(),
+        }
+    }
+
+    /// Whether unattended-upgrades is installed and enabled with `APT::Periodic::Unattended-Upgrade`.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether unattended-upgrades would install the candidate of `package` from one of `origins`.
+    pub fn allows(&self, package: &str, origins: &[Origin]) -> bool {
+        self.enabled
+            && !self.blacklist.iter().any(|re| re.is_match(package))
+            && origins.iter().any(|origin| {
+                self.allowed_origins
+                    .iter()
+                    .any(|allowed| matches_allowed_origin(allowed, origin))
+                    || self
+                        .origins_pattern
+                        .iter()
+                        .any(|pattern| matches_origins_pattern(pattern, origin))
+            })
+    }
+}
+
+/// `origin:archive`, where the archive may also be a codename.
+fn matches_allowed_origin(allowed: &str, origin: &Origin) -> bool {
+    match allowed.rsplit_once(':') {
+        Some((o, a)) => o == origin.origin && (a == origin.archive || a == origin.codename),
+        None => false,
+    }
+}
+
+/// Comma separated `key=value` pairs, where the values may contain wildcards.
+fn matches_origins_pattern(pattern: &str, origin: &Origin) -> bool {
+    pattern
+        .replace("\\,", "\u{0}")
+        .split(',')
+        .map(|pair| pair.replace('\u{0}', ","))
+        .all(|pair| {
+            let Some((key, value)) = pair.split_once('=') else {
+                return false;
+            };
+
+            let field = match key.trim() {
+                "origin" | "o" => &origin.origin,
+                "label" | "l" => &origin.label,
+                "archive" | "suite" | "a" => &origin.archive,
+                "codename" | "n" => &origin.codename,
+                "component" | "c" => &origin.component,
+                "site" => &origin.site,
+                _ => return false,
+            };
+
+            glob::Pattern::new(value.trim())
+                .map(|p| p.matches(field))
+                .unwrap_or(false)
+        })
+}
+
+/// `${distro_id}` and `${distro_codename}`, e.g. `Debian` and `bookworm`.
+fn distro() -> (String, String) {
+    let os_release = util::read_os_release(util::in_root("/etc/os-release"));
+
+    let id = os_release.get("ID").cloned().unwrap_or_default();
+    let mut chars = id.chars();
+    let id = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => id,
+    };
+
+    (
+        id,
+        os_release.get("VERSION_CODENAME").cloned().unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn security() -> Origin {
+        Origin {
+            origin: String::from("Debian"),
+            archive: String::from("stable-security"),
+            codename: String::from("bookworm-security"),
+            label: String::from("Debian-Security"),
+            component: String::from("main"),
+            site: String::from("security.debian.org"),
+        }
+    }
+
+    fn policy(allowed_origins: &[&str], origins_pattern: &[&str], blacklist: &[&str]) -> Policy {
+        Policy {
+            enabled: true,
+            allowed_origins: allowed_origins.iter().map(|s| s.to_string()).collect(),
+            origins_pattern: origins_pattern.iter().map(|s| s.to_string()).collect(),
+            blacklist: blacklist
+                .iter()
+                .map(|pattern| regex::Regex::new(&format!("^(?:{})", pattern)).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn allowed_origin_by_archive_or_codename() {
+        assert!(matches_allowed_origin("Debian:stable-security", &security()));
+        assert!(matches_allowed_origin("Debian:bookworm-security", &security()));
+        assert!(!matches_allowed_origin("Debian:bookworm", &security()));
+        assert!(!matches_allowed_origin("Debian", &security()));
+    }
+
+    #[test]
+    fn origins_pattern() {
+        assert!(matches_origins_pattern(
+            "origin=Debian,codename=bookworm,label=Debian-Security",
+            &Origin {
+                codename: String::from("bookworm"),
+                ..security()
+            }
+        ));
+        assert!(matches_origins_pattern("o=Debian,a=*-security", &security()));
+        assert!(!matches_origins_pattern("o=Debian,a=*-updates", &security()));
+        assert!(!matches_origins_pattern("unknown=Debian", &security()));
+    }
+
+    #[test]
+    fn origins_pattern_escaped_comma() {
+        let origin = Origin {
+            label: String::from("Vendor, Inc."),
+            ..security()
+        };
+
+        assert!(matches_origins_pattern("l=Vendor\\, Inc.", &origin));
+    }
+
+    #[test]
+    fn blacklist_anchored_at_start() {
+        let policy = policy(&["Debian:bookworm-security"], &[], &["linux-", "vim$"]);
+
+        assert!(!policy.allows("linux-image-amd64", &[security()]));
+        assert!(!policy.allows("vim", &[security()]));
+        assert!(policy.allows("vim-tiny", &[security()]));
+        assert!(policy.allows("libssl3", &[security()]));
+    }
+
+    #[test]
+    fn disabled_allows_nothing() {
+        let policy = Policy {
+            enabled: false,
+            ..policy(&["Debian:bookworm-security"], &[], &[])
+        };
+
+        assert!(!policy.allows("libssl3", &[security()]));
+    }
+}
@@ -1,5 +1,56 @@
 use nix::unistd::gethostname;
+use rust_apt::config::Config;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 pub fn get_hostname() -> Option<String> {
     gethostname().ok().and_then(|h| h.into_string().ok())
-}
\ No newline at end of file
+}
+
+/// The `RootDir` set with [`crate::apt::set_root`], if any.
+pub fn root() -> Option<PathBuf> {
+    let root = Config::new().find("RootDir", "");
+
+    if root.is_empty() || root == "/" {
+        None
+    } else {
+        Some(PathBuf::from(root))
+    }
+}
+
+/// `path` inside the root set with [`crate::apt::set_root`].
+pub fn in_root(path: &str) -> PathBuf {
+    match root() {
+        Some(root) => root.join(path.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The hostname, or the root's `PRETTY_NAME` from os-release followed by its path.
+pub fn host_label() -> Option<String> {
+    let Some(root) = root() else {
+        return get_hostname();
+    };
+
+    let os_release = read_os_release(in_root("/etc/os-release"));
+    let name = os_release
+        .get("PRETTY_NAME")
+        .or(os_release.get("NAME"))
+        .cloned()
+        .unwrap_or_else(|| String::from("unknown"));
+
+    Some(format!("{} ({})", name, root.display()))
+}
+
+/// Reads the `KEY=value` pairs from an os-release(5) file.
+pub fn read_os_release<P: AsRef<Path>>(path: P) -> BTreeMap<String, String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
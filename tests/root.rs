@@ -0,0 +1,191 @@
+//! `apt::set_root` replaces the process-wide apt configuration, so these tests run one at a time.
+
+use apt_updates_notifier::{apt, phasing, unattended};
+use rust_apt::config::Config;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+static APT_CONFIG: Mutex<()> = Mutex::new(());
+
+/// Creates a root containing `files`, plus an empty dpkg status.
+fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!(
+        "apt-updates-notifier-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&root);
+
+    for (path, contents) in [("var/lib/dpkg/status", "")].iter().chain(files) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    root
+}
+
+#[test]
+fn reads_the_root_apt_configuration() {
+    let _lock = APT_CONFIG.lock().unwrap_or_else(|err| err.into_inner());
+
+    let root = fixture(
+        "conf",
+        &[(
+            "etc/apt/apt.conf.d/50fixture",
+            "Unattended-Upgrade::Allowed-Origins { \"Fixture:stable\"; };\n",
+        )],
+    );
+
+    // As if it were in the host's apt.conf.d
+    Config::new().set("Fixture::Host", "true");
+
+    apt::set_root(&root).unwrap();
+
+    let config = Config::new();
+    assert_eq!(
+        config.find_vector("Unattended-Upgrade::Allowed-Origins"),
+        vec![String::from("Fixture:stable")]
+    );
+    assert!(!config.contains("Fixture::Host"));
+    assert_eq!(
+        Path::new(&config.find("RootDir", "")),
+        root.canonicalize().unwrap()
+    );
+
+    apt::set_root(Path::new("/")).unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn targets_do_not_share_configuration() {
+    let _lock = APT_CONFIG.lock().unwrap_or_else(|err| err.into_inner());
+
+    let unattended = fixture(
+        "unattended",
+        &[
+            ("usr/bin/unattended-upgrade", ""),
+            (
+                "etc/apt/apt.conf.d/50fixture",
+                "APT::Periodic::Unattended-Upgrade \"1\";\n\
+                 Unattended-Upgrade::Allowed-Origins { \"Fixture:stable\"; };\n\
+                 APT::Get::Never-Include-Phased-Updates \"true\";\n",
+            ),
+        ],
+    );
+    let manual = fixture("manual", &[]);
+
+    let origin = unattended::Origin {
+        origin: String::from("Fixture"),
+        archive: String::from("stable"),
+        ..Default::default()
+    };
+    let annotations = |root: &Path| {
+        apt::set_root(root).unwrap();
+
+        (
+            unattended::Policy::from_apt_config().allows("libssl3", std::slice::from_ref(&origin)),
+            phasing::Policy::from_apt_config().defers("openssl", "3.0.13-1", 50, false),
+        )
+    };
+
+    assert_eq!(annotations(&unattended), (true, true));
+    assert_eq!(annotations(&manual), (false, false));
+    assert_eq!(annotations(&unattended), (true, true));
+
+    apt::set_root(Path::new("/")).unwrap();
+    fs::remove_dir_all(unattended).unwrap();
+    fs::remove_dir_all(manual).unwrap();
+}
+
+/// A dpkg status or Packages stanza.
+fn stanza(package: &str, version: &str, extra: &str) -> String {
+    format!(
+        "Package: {}\nVersion: {}\nArchitecture: amd64\nMaintainer: Fixture <fixture@example.com>\n\
+         Installed-Size: 10\nSection: misc\nPriority: optional\n{}Description: Fixture\n\n",
+        package, version, extra
+    )
+}
+
+#[test]
+fn packages_pinned_below_the_newest_version() {
+    let _lock = APT_CONFIG.lock().unwrap_or_else(|err| err.into_inner());
+
+    let installed = ["fixture-free", "fixture-pinned", "fixture-low"]
+        .map(|package| stanza(package, "1.0", "Status: install ok installed\n"))
+        .concat();
+    let available = ["fixture-free", "fixture-pinned", "fixture-low"]
+        .map(|package| {
+            stanza(
+                package,
+                "2.0",
+                &format!("Filename: pool/{}_2.0_amd64.deb\nSize: 1000\n", package),
+            )
+        })
+        .concat();
+
+    let root = fixture(
+        "pinning",
+        &[
+            ("var/lib/dpkg/status", &installed),
+            (
+                "etc/apt/apt.conf.d/50fixture",
+                "APT::Architecture \"amd64\";\n\
+                 APT::Architectures { \"amd64\"; };\n\
+                 Dir::Cache::pkgcache \"\";\n\
+                 Dir::Cache::srcpkgcache \"\";\n",
+            ),
+            (
+                "etc/apt/sources.list",
+                "deb [trusted=yes] http://fixture.invalid/debian stable main\n",
+            ),
+            (
+                "var/lib/apt/lists/fixture.invalid_debian_dists_stable_Release",
+                "Origin: Fixture\nLabel: Fixture\nSuite: stable\nCodename: stable\n\
+                 Architectures: amd64\nComponents: main\n",
+            ),
+            (
+                "var/lib/apt/lists/fixture.invalid_debian_dists_stable_main_binary-amd64_Packages",
+                &available,
+            ),
+            (
+                "etc/apt/preferences.d/fixture",
+                // Installed versions are pinned at 100, so 2.0 loses either way
+                "Package: fixture-pinned\nPin: version 1.*\nPin-Priority: 1001\n\n\
+                 Package: fixture-low\nPin: release a=stable\nPin-Priority: 50\n",
+            ),
+        ],
+    );
+
+    apt::set_root(&root).unwrap();
+    let packages = apt::list_upgradeable().unwrap();
+    apt::set_root(Path::new("/")).unwrap();
+    fs::remove_dir_all(root).unwrap();
+
+    let summary: Vec<(&str, Option<&str>, bool, Option<&str>)> = packages
+        .iter()
+        .map(|pkg| {
+            (
+                pkg.name.as_str(),
+                pkg.candidate.as_deref(),
+                pkg.pinned,
+                pkg.available.as_deref(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        summary,
+        vec![
+            ("fixture-free", Some("2.0"), false, None),
+            ("fixture-low", Some("1.0"), true, Some("2.0")),
+            ("fixture-pinned", Some("1.0"), true, Some("2.0")),
+        ]
+    );
+    assert!(packages.iter().all(|pkg| !pkg.held));
+    assert_eq!(packages[1].download_size, 0);
+    assert_eq!(packages[1].pin_priority, Some(100));
+}